@@ -0,0 +1,167 @@
+//! `#[derive(MixpanelEvent)]` for `mixpanel_rs`. Enable the `derive` feature
+//! of `mixpanel_rs` rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Implements `mixpanel_rs::MixpanelEvent` for a struct with named fields.
+///
+/// The event name defaults to the struct name in snake case and is set with
+/// `#[mixpanel(name = "...")]` on the struct. Each field becomes a property
+/// named after the field; `None` values are left out. Field attributes:
+///
+/// - `rename = "..."` sends the field under another property name.
+/// - `skip` leaves the field out.
+/// - `flatten` merges the properties of a field that serializes to an object.
+/// - `distinct_id`, `device_id`, `user_id`, `insert_id`, `ip`, `os` bind the
+///   field to the matching reserved property.
+/// - `time` binds a `DateTime<Utc>` or epoch milliseconds field to the event
+///   time.
+#[proc_macro_derive(MixpanelEvent, attributes(mixpanel))]
+pub fn derive_mixpanel_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a field is turned into event properties.
+enum Binding {
+    Property(String),
+    Flatten,
+    Time,
+    Skip,
+}
+
+/// Reserved fields and the property each one is sent as.
+const RESERVED: &[(&str, &str)] = &[
+    ("distinct_id", "distinct_id"),
+    ("device_id", "$device_id"),
+    ("user_id", "$user_id"),
+    ("insert_id", "$insert_id"),
+    ("ip", "ip"),
+    ("os", "$os"),
+];
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let ident = &input.ident;
+    let name = event_name(&input)?;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "MixpanelEvent needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "MixpanelEvent can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut inserts = Vec::new();
+    for field in fields {
+        let member = field.ident.as_ref().expect("named field");
+        let insert = match field_binding(field)? {
+            Binding::Property(key) => quote! {
+                ::mixpanel_rs::event::__private::insert(&mut properties, #key, &self.#member)?;
+            },
+            Binding::Flatten => quote! {
+                ::mixpanel_rs::event::__private::flatten(&mut properties, &self.#member)?;
+            },
+            Binding::Time => quote! {
+                ::mixpanel_rs::event::__private::insert_time(&mut properties, &self.#member);
+            },
+            Binding::Skip => continue,
+        };
+        inserts.push(insert);
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::mixpanel_rs::event::MixpanelEvent for #ident #ty_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn to_event(
+                &self,
+            ) -> ::std::result::Result<::mixpanel_rs::Event, ::mixpanel_rs::errors::MixpanelError> {
+                let mut properties = ::mixpanel_rs::event::__private::Properties::new();
+                #(#inserts)*
+                ::std::result::Result::Ok(::mixpanel_rs::event::__private::into_event(
+                    <Self as ::mixpanel_rs::event::MixpanelEvent>::NAME,
+                    properties,
+                ))
+            }
+        }
+    })
+}
+
+fn event_name(input: &DeriveInput) -> syn::Result<String> {
+    let mut name = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("mixpanel")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(name.unwrap_or_else(|| snake_case(&input.ident.to_string())))
+}
+
+fn field_binding(field: &syn::Field) -> syn::Result<Binding> {
+    let member = field.ident.as_ref().expect("named field");
+    let mut binding = None;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("mixpanel")) {
+        attr.parse_nested_meta(|meta| {
+            let next = if meta.path.is_ident("rename") {
+                Binding::Property(meta.value()?.parse::<LitStr>()?.value())
+            } else if meta.path.is_ident("skip") {
+                Binding::Skip
+            } else if meta.path.is_ident("flatten") {
+                Binding::Flatten
+            } else if meta.path.is_ident("time") {
+                Binding::Time
+            } else if let Some((_, key)) = RESERVED.iter().find(|(n, _)| meta.path.is_ident(n)) {
+                Binding::Property(key.to_string())
+            } else {
+                return Err(meta.error("unknown mixpanel field attribute"));
+            };
+            if binding.replace(next).is_some() {
+                return Err(meta.error("a field takes only one mixpanel attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(binding.unwrap_or_else(|| Binding::Property(member.unraw().to_string())))
+}
+
+/// `VideoImpressionEventData` becomes `video_impression_event_data`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let chars: Vec<char> = name.chars().collect();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower =
+                i > 0 && (chars[i - 1].is_lowercase() || chars[i - 1].is_ascii_digit());
+            let before_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if i > 0 && (after_lower || (chars[i - 1].is_uppercase() && before_lower)) {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -1,4 +1,4 @@
-use mixpanel_rs::Mixpanel;
+use mixpanel_rs::{Event, Mixpanel};
 use serde_json::json;
 
 #[cfg(feature = "tracing")]
@@ -61,14 +61,12 @@ async fn main() {
         .await;
 
     let _ = mixpanel
-        .track(
-            "example_event-5",
-            Some(json!({
-                "distinct_id": distinct_id,
-                "button": "home",
-                "$device_id": &device_id_1,
-                "$user_id": distinct_id,
-            })),
+        .track_event(
+            Event::new("example_event-5")
+                .distinct_id(distinct_id)
+                .device_id(&device_id_1)
+                .user_id(distinct_id)
+                .property("button", "home"),
         )
         .await;
 }
@@ -0,0 +1,350 @@
+//! Synchronous versions of `Mixpanel`, `MixpanelPeople` and `MixpanelGroups`.
+//!
+//! They wrap the async types, so payloads are built by exactly the same
+//! code, and drive them on the calling thread through a blocking HTTP
+//! client. Like `reqwest::blocking`, they must not be called from inside an
+//! async runtime.
+
+use crate::errors::MixpanelError;
+use crate::event::MixpanelEvent;
+use crate::types::{BatchResult, Config, Event, ImportResponse, TrackResponse};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+#[derive(Clone)]
+pub struct Mixpanel {
+    inner: crate::Mixpanel,
+    pub people: MixpanelPeople,
+    pub groups: MixpanelGroups,
+}
+
+#[derive(Clone)]
+pub struct MixpanelPeople {
+    inner: crate::people::MixpanelPeople,
+}
+
+#[derive(Clone)]
+pub struct MixpanelGroups {
+    inner: crate::groups::MixpanelGroups,
+}
+
+/// A `Config` whose transport works without an async runtime, made by
+/// `ConfigBuilder::build_blocking`.
+#[derive(Debug, Clone)]
+pub struct BlockingConfig(Config);
+
+impl BlockingConfig {
+    pub(crate) fn new(config: Config) -> Self {
+        Self(config)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.0
+    }
+}
+
+impl Mixpanel {
+    /// Creates a client; without a `config`, the defaults are used.
+    pub fn init(token: &str, config: Option<BlockingConfig>) -> Self {
+        let config = config.unwrap_or_else(|| {
+            Config::builder()
+                .build_blocking()
+                .expect("default Mixpanel config is valid")
+        });
+        let inner = crate::Mixpanel::init(token, Some(config.0));
+        Self {
+            people: MixpanelPeople {
+                inner: inner.people.clone(),
+            },
+            groups: MixpanelGroups {
+                inner: inner.groups.clone(),
+            },
+            inner,
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.inner.token
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    pub fn register(&self, properties: Value) {
+        self.inner.register(properties)
+    }
+
+    pub fn register_once(&self, properties: Value) {
+        self.inner.register_once(properties)
+    }
+
+    pub fn unregister(&self, key: &str) {
+        self.inner.unregister(key)
+    }
+
+    pub fn time_event(&self, event: &str, key: &str) {
+        self.inner.time_event(event, key)
+    }
+
+    pub fn track(
+        &self,
+        event: &str,
+        properties: Option<Value>,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.track(event, properties))
+    }
+
+    pub fn track_event(&self, event: Event) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.track_event(event))
+    }
+
+    pub fn track_typed<E: MixpanelEvent>(&self, event: &E) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.track_typed(event))
+    }
+
+    pub fn track_batch(&self, events: Vec<Event>) -> Vec<BatchResult> {
+        block_on(self.inner.track_batch(events))
+    }
+
+    pub fn alias(&self, distinct_id: &str, alias: &str) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.alias(distinct_id, alias))
+    }
+
+    pub fn identify(&self, old_id: &str, new_id: &str) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.identify(old_id, new_id))
+    }
+
+    pub fn merge(
+        &self,
+        distinct_id_1: &str,
+        distinct_id_2: &str,
+    ) -> Result<ImportResponse, MixpanelError> {
+        block_on(self.inner.merge(distinct_id_1, distinct_id_2))
+    }
+
+    pub fn import(
+        &self,
+        event: &str,
+        time: i64,
+        properties: Value,
+    ) -> Result<ImportResponse, MixpanelError> {
+        block_on(self.inner.import(event, time, properties))
+    }
+
+    pub fn import_batch(&self, events: Vec<Event>) -> Vec<BatchResult<ImportResponse>> {
+        block_on(self.inner.import_batch(events))
+    }
+}
+
+impl MixpanelPeople {
+    pub fn set(
+        &self,
+        distinct_id: &str,
+        ip: Value,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.set(distinct_id, ip, properties))
+    }
+
+    pub fn increment(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.increment(distinct_id, properties))
+    }
+
+    pub fn append(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.append(distinct_id, properties))
+    }
+
+    pub fn set_once(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.set_once(distinct_id, properties))
+    }
+
+    pub fn unset(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.unset(distinct_id, properties))
+    }
+
+    pub fn remove(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.remove(distinct_id, properties))
+    }
+
+    pub fn union(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.union(distinct_id, properties))
+    }
+
+    pub fn delete_user(
+        &self,
+        distinct_id: &str,
+        ignore_alias: bool,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.delete_user(distinct_id, ignore_alias))
+    }
+
+    pub fn track_charge(
+        &self,
+        distinct_id: &str,
+        amount: f64,
+        properties: Option<Value>,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.track_charge(distinct_id, amount, properties))
+    }
+
+    pub fn clear_charges(&self, distinct_id: &str) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.clear_charges(distinct_id))
+    }
+
+    pub fn batch(&self, updates: Vec<Value>) -> Vec<BatchResult> {
+        block_on(self.inner.batch(updates))
+    }
+}
+
+impl MixpanelGroups {
+    pub fn set(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.set(group_key, group_id, properties))
+    }
+
+    pub fn set_once(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.set_once(group_key, group_id, properties))
+    }
+
+    pub fn unset(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.unset(group_key, group_id, properties))
+    }
+
+    pub fn remove(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.remove(group_key, group_id, properties))
+    }
+
+    pub fn union(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.union(group_key, group_id, properties))
+    }
+
+    pub fn delete_group(
+        &self,
+        group_key: &str,
+        group_id: &str,
+    ) -> Result<TrackResponse, MixpanelError> {
+        block_on(self.inner.delete_group(group_key, group_id))
+    }
+
+    pub fn set_batch(&self, group_key: &str, groups: Vec<(String, Value)>) -> Vec<BatchResult> {
+        block_on(self.inner.set_batch(group_key, groups))
+    }
+
+    pub fn batch(&self, updates: Vec<Value>) -> Vec<BatchResult> {
+        block_on(self.inner.batch(updates))
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::RetryPolicy;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_blocking_client_shares_async_payloads() {
+        let transport = RecordingTransport::new();
+        transport.push_response(503, "unavailable");
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .retry(RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                jitter: false,
+                ..RetryPolicy::default()
+            })
+            .build_blocking()
+            .unwrap();
+        let mixpanel = Mixpanel::init("token", Some(config));
+
+        mixpanel
+            .track("signup", Some(json!({ "distinct_id": "user" })))
+            .unwrap();
+        mixpanel
+            .people
+            .set_once("user", json!({ "plan": "free" }))
+            .unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        let event = &requests[1].json().unwrap()[0];
+        assert_eq!(event["properties"]["token"], json!("token"));
+        assert!(event["properties"]["$insert_id"].is_string());
+        let update = &requests[2].json().unwrap()[0];
+        assert_eq!(update["$set_once"], json!({ "plan": "free" }));
+    }
+}
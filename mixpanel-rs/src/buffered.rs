@@ -0,0 +1,363 @@
+use crate::client::Mixpanel;
+use crate::errors::MixpanelError;
+use crate::types::{BatchResult, Event, MAX_BATCH_SIZE};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "tracing")]
+use tracing::error;
+
+/// What `BufferedMixpanel::enqueue` does when the buffer is at capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Discard the oldest buffered event to make room.
+    DropOldest,
+    /// Wait until the background task has freed up space.
+    Block,
+    /// Fail with `MixpanelError::BufferFull`.
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct BufferConfig {
+    /// Maximum number of events held in memory.
+    pub capacity: usize,
+    /// Flush as soon as this many events are buffered. Capped at `capacity`
+    /// and `MAX_BATCH_SIZE`.
+    pub flush_size: usize,
+    /// Flush whatever is buffered at least this often.
+    pub flush_interval: Duration,
+    pub backpressure: BackpressurePolicy,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            flush_size: 500,
+            flush_interval: Duration::from_secs(5),
+            backpressure: BackpressurePolicy::DropOldest,
+        }
+    }
+}
+
+enum Command {
+    Flush(oneshot::Sender<Vec<BatchResult>>),
+    Shutdown(oneshot::Sender<Vec<BatchResult>>),
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Event>>,
+    /// Signalled by the background task whenever it drains the queue.
+    space: Notify,
+    /// Signalled by `enqueue` when `flush_size` is reached.
+    wake: Notify,
+    closed: AtomicBool,
+    dropped: AtomicU64,
+}
+
+/// A `Mixpanel` front-end that buffers events in memory and sends them in
+/// batches from a background tokio task.
+///
+/// `enqueue` returns as soon as the event is buffered. Buffered events are
+/// sent when `flush_size` is reached, every `flush_interval`, on `flush`, and
+/// on `shutdown`, which drains the buffer before stopping the task.
+#[derive(Clone)]
+pub struct BufferedMixpanel {
+    mixpanel: Mixpanel,
+    shared: Arc<Shared>,
+    config: BufferConfig,
+    commands: mpsc::UnboundedSender<Command>,
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl BufferedMixpanel {
+    /// Starts the background task. Must be called from within a tokio runtime.
+    pub fn new(mixpanel: Mixpanel, mut config: BufferConfig) -> Self {
+        config.capacity = config.capacity.max(1);
+        config.flush_size = config
+            .flush_size
+            .clamp(1, config.capacity.min(MAX_BATCH_SIZE));
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            space: Notify::new(),
+            wake: Notify::new(),
+            closed: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+        });
+        let (commands, rx) = mpsc::unbounded_channel();
+        let worker = tokio::spawn(run(mixpanel.clone(), shared.clone(), config.clone(), rx));
+        Self {
+            mixpanel,
+            shared,
+            config,
+            commands,
+            worker: Arc::new(Mutex::new(Some(worker))),
+        }
+    }
+
+    /// Buffers an event for sending. The event `time`, super properties and
+    /// `$duration` are applied here, so they reflect when the event happened
+    /// rather than when it was flushed.
+    pub async fn enqueue(&self, mut event: Event) -> Result<(), MixpanelError> {
+        self.mixpanel.apply_context(&mut event);
+        if event.properties.get("time").is_none() {
+            event.properties["time"] = json!(chrono::Utc::now().timestamp_millis());
+        }
+        loop {
+            if self.shared.closed.load(Ordering::Acquire) {
+                return Err(MixpanelError::BufferClosed);
+            }
+            let space = {
+                let mut queue = self.shared.queue.lock().unwrap();
+                if queue.len() < self.config.capacity {
+                    queue.push_back(event);
+                    if queue.len() >= self.config.flush_size {
+                        self.shared.wake.notify_one();
+                    }
+                    return Ok(());
+                }
+                match self.config.backpressure {
+                    BackpressurePolicy::DropOldest => {
+                        queue.pop_front();
+                        queue.push_back(event);
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.shared.wake.notify_one();
+                        return Ok(());
+                    }
+                    BackpressurePolicy::Error => return Err(MixpanelError::BufferFull),
+                    // Created while holding the lock so a drain that happens
+                    // right after it is released still wakes us.
+                    BackpressurePolicy::Block => self.shared.space.notified(),
+                }
+            };
+            self.shared.wake.notify_one();
+            space.await;
+        }
+    }
+
+    /// Sends everything buffered so far and waits for the result.
+    pub async fn flush(&self) -> Result<Vec<BatchResult>, MixpanelError> {
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Flush(tx))
+            .map_err(|_| MixpanelError::BufferClosed)?;
+        rx.await.map_err(|_| MixpanelError::BufferClosed)
+    }
+
+    /// Stops accepting events, sends everything still buffered and waits for
+    /// the background task to exit.
+    pub async fn shutdown(&self) -> Result<Vec<BatchResult>, MixpanelError> {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.space.notify_waiters();
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Shutdown(tx))
+            .map_err(|_| MixpanelError::BufferClosed)?;
+        let results = rx.await.map_err(|_| MixpanelError::BufferClosed)?;
+        let worker = self.worker.lock().unwrap().take();
+        if let Some(worker) = worker {
+            let _ = worker.await;
+        }
+        Ok(results)
+    }
+
+    /// Number of events currently buffered.
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of events discarded by `BackpressurePolicy::DropOldest`.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+async fn run(
+    mixpanel: Mixpanel,
+    shared: Arc<Shared>,
+    config: BufferConfig,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+) {
+    let start = tokio::time::Instant::now() + config.flush_interval;
+    let mut ticker = tokio::time::interval_at(start, config.flush_interval);
+    loop {
+        // Commands go first so `flush` reports on everything buffered before it
+        tokio::select! {
+            biased;
+            command = commands.recv() => match command {
+                Some(Command::Flush(reply)) => {
+                    let _ = reply.send(send(&mixpanel, &shared, 0).await);
+                }
+                Some(Command::Shutdown(reply)) => {
+                    let _ = reply.send(send(&mixpanel, &shared, 0).await);
+                    break;
+                }
+                None => {
+                    report(send(&mixpanel, &shared, 0).await);
+                    break;
+                }
+            },
+            _ = shared.wake.notified() => {
+                report(send(&mixpanel, &shared, config.flush_size).await);
+            }
+            _ = ticker.tick() => {
+                report(send(&mixpanel, &shared, 0).await);
+            }
+        }
+    }
+}
+
+/// Sends buffered events in batches for as long as at least `min` (and at
+/// least one) are waiting.
+async fn send(mixpanel: &Mixpanel, shared: &Shared, min: usize) -> Vec<BatchResult> {
+    let mut results = Vec::new();
+    loop {
+        let batch: Vec<Event> = {
+            let mut queue = shared.queue.lock().unwrap();
+            if queue.is_empty() || queue.len() < min {
+                break;
+            }
+            let len = queue.len().min(MAX_BATCH_SIZE);
+            queue.drain(..len).collect()
+        };
+        shared.space.notify_waiters();
+        results.extend(mixpanel.track_batch_with_context(batch).await);
+    }
+    results
+}
+
+fn report(results: Vec<BatchResult>) {
+    for result in results {
+        if let Err(_e) = result.result {
+            #[cfg(feature = "tracing")]
+            error!(offset = result.offset, len = result.len, error = %_e, "Failed to flush buffered Mixpanel events");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::Config;
+
+    fn buffered(transport: &RecordingTransport, config: BufferConfig) -> BufferedMixpanel {
+        let mixpanel = Mixpanel::init(
+            "token",
+            Some(
+                Config::builder()
+                    .transport(Arc::new(transport.clone()))
+                    .build()
+                    .unwrap(),
+            ),
+        );
+        BufferedMixpanel::new(mixpanel, config)
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_buffered_events_in_one_request() {
+        let transport = RecordingTransport::new();
+        let buffer = buffered(
+            &transport,
+            BufferConfig {
+                flush_interval: Duration::from_secs(3600),
+                ..BufferConfig::default()
+            },
+        );
+        for i in 0..3 {
+            buffer
+                .enqueue(Event::new("viewed").property("i", i))
+                .await
+                .unwrap();
+        }
+        let results = buffer.flush().await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].len, 3);
+        assert_eq!(transport.json_bodies()[0].as_array().unwrap().len(), 3);
+
+        buffer.shutdown().await.unwrap();
+        assert!(matches!(
+            buffer.enqueue(Event::new("late")).await,
+            Err(MixpanelError::BufferClosed)
+        ));
+    }
+
+    // `#[tokio::test]` runs on a single thread, so the worker cannot drain
+    // the queue until the test awaits something that yields.
+    #[tokio::test]
+    async fn test_full_buffer_applies_backpressure_policy() {
+        let transport = RecordingTransport::new();
+        let config = BufferConfig {
+            capacity: 2,
+            flush_interval: Duration::from_secs(3600),
+            backpressure: BackpressurePolicy::Error,
+            ..BufferConfig::default()
+        };
+        let rejecting = buffered(&transport, config.clone());
+        rejecting.enqueue(Event::new("a")).await.unwrap();
+        rejecting.enqueue(Event::new("b")).await.unwrap();
+        assert!(matches!(
+            rejecting.enqueue(Event::new("c")).await,
+            Err(MixpanelError::BufferFull)
+        ));
+
+        let dropping = buffered(
+            &transport,
+            BufferConfig {
+                backpressure: BackpressurePolicy::DropOldest,
+                ..config
+            },
+        );
+        for name in ["a", "b", "c"] {
+            dropping.enqueue(Event::new(name)).await.unwrap();
+        }
+        assert_eq!(dropping.dropped(), 1);
+        assert_eq!(dropping.len(), 2);
+
+        dropping.flush().await.unwrap();
+        let sent = transport.json_bodies().pop().unwrap();
+        assert_eq!(sent[0]["event"], json!("b"));
+        assert_eq!(sent[1]["event"], json!("c"));
+    }
+
+    #[tokio::test]
+    async fn test_context_is_applied_once_at_enqueue() {
+        let transport = RecordingTransport::new();
+        let buffer = buffered(
+            &transport,
+            BufferConfig {
+                flush_interval: Duration::from_secs(3600),
+                ..BufferConfig::default()
+            },
+        );
+        buffer
+            .enqueue(Event::new("upload").distinct_id("user"))
+            .await
+            .unwrap();
+        buffer.mixpanel.register(json!({ "late": true }));
+        buffer.mixpanel.time_event("upload", "user");
+        buffer.flush().await.unwrap();
+
+        let sent = transport.json_bodies().pop().unwrap();
+        assert!(sent[0]["properties"].get("late").is_none());
+        assert!(sent[0]["properties"].get("$duration").is_none());
+        // The timer is still running for the next upload.
+        buffer
+            .mixpanel
+            .track_event(Event::new("upload").distinct_id("user"))
+            .await
+            .unwrap();
+        let sent = transport.json_bodies().pop().unwrap();
+        assert!(sent[0]["properties"]["$duration"].is_f64());
+    }
+}
@@ -1,9 +1,29 @@
+use crate::event::MixpanelEvent;
+use crate::gdpr::MixpanelGdpr;
 use crate::groups::MixpanelGroups;
-use crate::types::Config;
-use crate::utils::send_request;
+use crate::lexicon::MixpanelLexicon;
+use crate::lookup_tables::MixpanelLookupTables;
+use crate::query::MixpanelQuery;
+use crate::types::{BatchResult, Config, Event, IdMerge, ImportResponse, TrackResponse};
+use crate::utils::{
+    send_batch_request, send_import_batch_request, send_import_request, send_request,
+};
+use crate::validation::validate_event;
 use crate::{errors::MixpanelError, people::MixpanelPeople};
-use serde_json::{json, Value};
-use std::sync::Arc;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Key of a running `time_event` timer: event name and distinct id.
+type TimerKey = (String, String);
+
+/// Most timers kept at once. Starting another one first drops the expired
+/// timers, then the oldest.
+pub(crate) const MAX_TIMERS: usize = 10_000;
+
+/// How long a timer waits for its event before it is discarded.
+pub(crate) const TIMER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
 #[derive(Clone)]
 pub struct Mixpanel {
@@ -11,6 +31,12 @@ pub struct Mixpanel {
     pub config: Arc<Config>,
     pub people: MixpanelPeople,
     pub groups: MixpanelGroups,
+    pub query: MixpanelQuery,
+    pub lookup_tables: MixpanelLookupTables,
+    pub lexicon: MixpanelLexicon,
+    pub gdpr: MixpanelGdpr,
+    super_properties: Arc<RwLock<Map<String, Value>>>,
+    timers: Arc<Mutex<HashMap<TimerKey, Instant>>>,
 }
 
 impl Mixpanel {
@@ -20,20 +46,203 @@ impl Mixpanel {
             token: token.to_string(),
             people: MixpanelPeople::new(token, config.clone()),
             groups: MixpanelGroups::new(token, config.clone()),
+            query: MixpanelQuery::new(config.clone()),
+            lookup_tables: MixpanelLookupTables::new(config.clone()),
+            lexicon: MixpanelLexicon::new(config.clone()),
+            gdpr: MixpanelGdpr::new(token, config.clone()),
             config,
+            super_properties: Arc::default(),
+            timers: Arc::default(),
+        }
+    }
+
+    /// Registers super properties, which are added to every tracked event.
+    /// Properties passed to `track` take precedence over them. Clones of this
+    /// client share the same super properties.
+    pub fn register(&self, properties: Value) {
+        if let Value::Object(properties) = properties {
+            self.super_properties.write().unwrap().extend(properties);
+        }
+    }
+
+    /// Like `register`, but keeps the current value of super properties that
+    /// are already registered.
+    pub fn register_once(&self, properties: Value) {
+        if let Value::Object(properties) = properties {
+            let mut super_properties = self.super_properties.write().unwrap();
+            for (key, value) in properties {
+                super_properties.entry(key).or_insert(value);
+            }
         }
     }
 
+    pub fn unregister(&self, key: &str) {
+        self.super_properties.write().unwrap().remove(key);
+    }
+
+    /// Starts a timer for the next `event` tracked with `distinct_id` set to
+    /// `key`. That event gets the elapsed seconds as `$duration`, unless it
+    /// comes more than `TIMER_TTL` later. At most `MAX_TIMERS` are kept.
+    pub fn time_event(&self, event: &str, key: &str) {
+        let mut timers = self.timers.lock().unwrap();
+        if timers.len() >= MAX_TIMERS {
+            timers.retain(|_, started| started.elapsed() < TIMER_TTL);
+        }
+        if timers.len() >= MAX_TIMERS {
+            let oldest = timers
+                .iter()
+                .min_by_key(|(_, started)| **started)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                timers.remove(&oldest);
+            }
+        }
+        timers.insert((event.to_string(), key.to_string()), Instant::now());
+    }
+
+    /// Applies super properties and `$duration` from a matching timer.
+    pub(crate) fn apply_context(&self, event: &mut Event) {
+        let Value::Object(props) = &mut event.properties else {
+            return;
+        };
+        for (key, value) in self.super_properties.read().unwrap().iter() {
+            props.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+
+        let distinct_id = props
+            .get("distinct_id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let started = self
+            .timers
+            .lock()
+            .unwrap()
+            .remove(&(event.name.clone(), distinct_id))
+            .filter(|started| started.elapsed() < TIMER_TTL);
+        if let Some(started) = started {
+            props
+                .entry("$duration")
+                .or_insert_with(|| json!(started.elapsed().as_secs_f64()));
+        }
+    }
+
+    /// Tracks an event from an untyped property object. Prefer `track_event`
+    /// when the properties are known up front.
     pub async fn track(
         &self,
         event: &str,
         properties: Option<Value>,
-    ) -> Result<Value, MixpanelError> {
-        let mut props = properties.unwrap_or_default();
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.track_event(Event::with_properties(
+            event,
+            properties.unwrap_or_default(),
+        ))
+        .await
+    }
+
+    /// Tracks an event. Events older than five days are no longer accepted
+    /// by `/track`, so they are sent through `/import` instead, which needs
+    /// `Config::credentials`.
+    pub async fn track_event(&self, event: Event) -> Result<TrackResponse, MixpanelError> {
+        let body = self.event_body(event)?;
+        if is_historical(&body) {
+            return send_import_request(&self.config, vec![body])
+                .await
+                .map(|_| accepted());
+        }
+        send_request(&self.config, "/track", body).await
+    }
+
+    /// Tracks a typed event, usually one with `#[derive(MixpanelEvent)]`.
+    pub async fn track_typed<E: MixpanelEvent>(
+        &self,
+        event: &E,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.track_event(event.to_event()?).await
+    }
+
+    /// Tracks many events at once, packing them into requests of up to
+    /// `MAX_BATCH_SIZE` events. Each event gets the same `time`/`$insert_id`
+    /// defaults, validation and `/import` routing as `track_event`; events
+    /// that fail validation get a result of their own and are not sent.
+    pub async fn track_batch(&self, events: Vec<Event>) -> Vec<BatchResult> {
+        let bodies = events
+            .into_iter()
+            .map(|event| self.event_body(event))
+            .collect();
+        self.send_bodies(bodies).await
+    }
+
+    /// Like `track_batch`, for events that already had super properties and
+    /// timers applied, so that sending them does not apply those again.
+    pub(crate) async fn track_batch_with_context(&self, events: Vec<Event>) -> Vec<BatchResult> {
+        let bodies = events.into_iter().map(|event| self.stamp(event)).collect();
+        self.send_bodies(bodies).await
+    }
+
+    async fn send_bodies(&self, bodies: Vec<Result<Value, MixpanelError>>) -> Vec<BatchResult> {
+        let mut results = Vec::new();
+        let mut bodies = bodies.into_iter().enumerate().peekable();
+        // Consecutive valid events that share a destination are sent
+        // together, so offsets keep pointing into `events`.
+        while let Some((offset, first)) = bodies.next() {
+            let first = match first {
+                Ok(body) => body,
+                Err(e) => {
+                    results.push(BatchResult {
+                        offset,
+                        len: 1,
+                        result: Err(e),
+                    });
+                    continue;
+                }
+            };
+            let historical = is_historical(&first);
+            let mut run = vec![first];
+            while let Some((_, Ok(body))) = bodies
+                .next_if(|(_, body)| matches!(body, Ok(body) if is_historical(body) == historical))
+            {
+                run.push(body);
+            }
+            let run_results = if historical {
+                send_import_batch_request(&self.config, run)
+                    .await
+                    .into_iter()
+                    .map(|r| BatchResult {
+                        offset: r.offset,
+                        len: r.len,
+                        result: r.result.map(|_| accepted()),
+                    })
+                    .collect()
+            } else {
+                send_batch_request(&self.config, "/track", run).await
+            };
+            results.extend(run_results.into_iter().map(|mut r| {
+                r.offset += offset;
+                r
+            }));
+        }
+        results
+    }
+
+    fn event_body(&self, mut event: Event) -> Result<Value, MixpanelError> {
+        self.apply_context(&mut event);
+        self.stamp(event)
+    }
+
+    /// Adds the token, `time` and `$insert_id` defaults and validates the
+    /// event. Identity events come here directly, so that super properties
+    /// and timers stay out of them.
+    fn stamp(&self, mut event: Event) -> Result<Value, MixpanelError> {
+        let props = &mut event.properties;
         props["token"] = json!(self.token);
+        if self.config.id_merge == IdMerge::Simplified {
+            derive_distinct_id(props);
+        }
         // Timestamp
         if props.get("time").is_none() {
-            props["time"] = json!(chrono::Utc::now().timestamp());
+            props["time"] = json!(chrono::Utc::now().timestamp_millis());
         }
 
         // Insert ID
@@ -41,14 +250,15 @@ impl Mixpanel {
             props["$insert_id"] = json!(uuid::Uuid::new_v4().to_string());
         }
 
-        let body = json!({
-            "event": event,
-            "properties": props,
-        });
-        send_request(&self.config, "/track", body).await
+        validate_event(&mut event, &self.config.validation)?;
+        Ok(event.into_body())
     }
 
-    pub async fn alias(&self, distinct_id: &str, alias: &str) -> Result<Value, MixpanelError> {
+    pub async fn alias(
+        &self,
+        distinct_id: &str,
+        alias: &str,
+    ) -> Result<TrackResponse, MixpanelError> {
         let props = json!({
             "distinct_id": distinct_id,
             "alias": alias,
@@ -57,28 +267,432 @@ impl Mixpanel {
         self.track("$create_alias", Some(props)).await
     }
 
-    pub async fn identify(&self, old_id: &str, new_id: &str) -> Result<Value, MixpanelError> {
-        let props = json!({
-            "$identified_id": new_id,
-            "$anon_id": old_id,
-            "token": self.token
-        });
-        let data = json!({
-            "event": "$identify",
-            "properties": props,
-        });
-        send_request(&self.config, "/track", data).await
+    /// Links the anonymous `old_id` to the user `new_id`.
+    ///
+    /// With Original ID Merge this is an `$identify` event from `$anon_id` to
+    /// `$identified_id`. With Simplified ID Merge `old_id` is the device id,
+    /// and the event carries it as `$device_id` next to `$user_id`.
+    pub async fn identify(
+        &self,
+        old_id: &str,
+        new_id: &str,
+    ) -> Result<TrackResponse, MixpanelError> {
+        let event = match self.config.id_merge {
+            IdMerge::Original => Event::new("$identify")
+                .distinct_id(new_id)
+                .property("$identified_id", new_id)
+                .property("$anon_id", old_id),
+            IdMerge::Simplified => Event::new("$identify").device_id(old_id).user_id(new_id),
+        };
+        send_request(&self.config, "/track", self.stamp(event)?).await
     }
 
+    /// Merges the identity clusters of two distinct ids with a `$merge`
+    /// event. Only Original ID Merge supports it, and it goes through
+    /// `/import`, so it needs `Config::credentials`.
+    pub async fn merge(
+        &self,
+        distinct_id_1: &str,
+        distinct_id_2: &str,
+    ) -> Result<ImportResponse, MixpanelError> {
+        if self.config.id_merge == IdMerge::Simplified {
+            return Err(MixpanelError::InvalidConfig(
+                "$merge is not supported with Simplified ID Merge".into(),
+            ));
+        }
+        let event =
+            Event::new("$merge").property("$distinct_ids", json!([distinct_id_1, distinct_id_2]));
+        send_import_request(&self.config, vec![self.stamp(event)?]).await
+    }
+
+    /// Imports a historical event through `/import`. Needs
+    /// `Config::credentials`.
     pub async fn import(
         &self,
         event: &str,
         time: i64,
         mut properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<ImportResponse, MixpanelError> {
         properties["time"] = json!(time);
-        properties["token"] = json!(self.token);
-        let body = json!({ "event": event, "properties": properties });
-        send_request(&self.config, "/import", body).await
+        self.import_batch(vec![Event::with_properties(event, properties)])
+            .await
+            .pop()
+            .map(|r| r.result)
+            .unwrap_or_else(|| Err(MixpanelError::Other("no import result".into())))
+    }
+
+    /// Imports events through `/import` in requests of up to
+    /// `MAX_BATCH_SIZE`, whatever their age.
+    pub async fn import_batch(&self, events: Vec<Event>) -> Vec<BatchResult<ImportResponse>> {
+        let mut results = Vec::new();
+        let mut bodies = events
+            .into_iter()
+            .map(|event| self.event_body(event))
+            .enumerate()
+            .peekable();
+        while let Some((offset, first)) = bodies.next() {
+            let first = match first {
+                Ok(body) => body,
+                Err(e) => {
+                    results.push(BatchResult {
+                        offset,
+                        len: 1,
+                        result: Err(e),
+                    });
+                    continue;
+                }
+            };
+            let mut run = vec![first];
+            while let Some((_, Ok(body))) = bodies.next_if(|(_, body)| body.is_ok()) {
+                run.push(body);
+            }
+            results.extend(
+                send_import_batch_request(&self.config, run)
+                    .await
+                    .into_iter()
+                    .map(|mut r| {
+                        r.offset += offset;
+                        r
+                    }),
+            );
+        }
+        results
+    }
+}
+
+/// Sets `distinct_id` the way Simplified ID Merge expects it when the event
+/// has none: the user id if known, the device id otherwise.
+fn derive_distinct_id(props: &mut Value) {
+    let has_distinct_id = props
+        .get("distinct_id")
+        .and_then(Value::as_str)
+        .is_some_and(|id| !id.is_empty());
+    if has_distinct_id {
+        return;
+    }
+    let user_id = props.get("$user_id").and_then(Value::as_str);
+    let device_id = props.get("$device_id").and_then(Value::as_str);
+    let distinct_id = match (user_id, device_id) {
+        (Some(user_id), _) => user_id.to_string(),
+        (None, Some(device_id)) => format!("$device:{device_id}"),
+        (None, None) => return,
+    };
+    props["distinct_id"] = json!(distinct_id);
+}
+
+/// How old an event can be and still be sent through `/track`.
+const TRACK_MAX_AGE_MS: i64 = 5 * 24 * 60 * 60 * 1000;
+
+/// Whether the event body's `time` is too old for `/track`. `time` may be in
+/// seconds or milliseconds.
+fn is_historical(body: &Value) -> bool {
+    let Some(time) = body["properties"]["time"].as_f64() else {
+        return false;
+    };
+    let time_ms = if time < 1e11 { time * 1000.0 } else { time };
+    (chrono::Utc::now().timestamp_millis() as f64 - time_ms) > TRACK_MAX_AGE_MS as f64
+}
+
+fn accepted() -> TrackResponse {
+    TrackResponse {
+        status: 1,
+        error: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::{Compression, Credentials, RetryPolicy, MAX_BATCH_SIZE};
+    use crate::validation::{ValidationConfig, ValidationMode};
+    use std::time::Duration;
+
+    fn mixpanel(transport: &RecordingTransport) -> Mixpanel {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .retry(RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                jitter: false,
+                ..RetryPolicy::default()
+            })
+            .build()
+            .unwrap();
+        Mixpanel::init("token", Some(config))
+    }
+
+    #[tokio::test]
+    async fn test_track_sends_verbose_payload_with_defaults() {
+        let transport = RecordingTransport::new();
+        let response = mixpanel(&transport)
+            .track("signup", Some(json!({ "distinct_id": "user" })))
+            .await
+            .unwrap();
+        assert_eq!(response.status, 1);
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url, "https://api.mixpanel.com/track?verbose=1");
+        let body = requests[0].json().unwrap();
+        let props = &body[0]["properties"];
+        assert_eq!(body[0]["event"], json!("signup"));
+        assert_eq!(props["distinct_id"], json!("user"));
+        assert_eq!(props["token"], json!("token"));
+        assert!(props["time"].is_i64());
+        assert!(props["$insert_id"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_track_batch_splits_at_limit() {
+        let transport = RecordingTransport::new();
+        let events = (0..MAX_BATCH_SIZE + 1)
+            .map(|i| Event::new("viewed").property("i", i))
+            .collect();
+        let results = mixpanel(&transport).track_batch(events).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].offset, results[0].len), (0, MAX_BATCH_SIZE));
+        assert_eq!((results[1].offset, results[1].len), (MAX_BATCH_SIZE, 1));
+        let bodies = transport.json_bodies();
+        assert_eq!(bodies[0].as_array().unwrap().len(), MAX_BATCH_SIZE);
+        assert_eq!(bodies[1][0]["properties"]["i"], json!(MAX_BATCH_SIZE));
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors_but_not_validation_errors() {
+        let transport = RecordingTransport::new();
+        transport
+            .push_response(503, "unavailable")
+            .push_error("timed out", true);
+        let mixpanel = mixpanel(&transport);
+        assert!(mixpanel.track("a", None).await.is_ok());
+        assert_eq!(transport.requests().len(), 3);
+
+        transport.push_response(400, "invalid");
+        let err = mixpanel.track("b", None).await.unwrap_err();
+        assert!(matches!(err, MixpanelError::ApiError { status, .. } if status == 400));
+        assert_eq!(transport.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_verbose_rejection_is_an_error() {
+        let transport = RecordingTransport::new();
+        transport.push_json(200, json!({ "status": 0, "error": "bad token" }));
+        let err = mixpanel(&transport).track("a", None).await.unwrap_err();
+        assert!(matches!(err, MixpanelError::Rejected(message) if message == "bad token"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_events_are_reported_without_sending() {
+        let transport = RecordingTransport::new();
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .validation(ValidationConfig {
+                mode: ValidationMode::Reject,
+                ..ValidationConfig::default()
+            })
+            .build()
+            .unwrap();
+        let mixpanel = Mixpanel::init("token", Some(config));
+        let events = vec![
+            Event::new("ok"),
+            Event::new("bad").property("title", "x".repeat(300)),
+            Event::new("ok"),
+        ];
+
+        let results = mixpanel.track_batch(events).await;
+
+        assert_eq!(results.len(), 3);
+        assert_eq!((results[0].offset, results[0].len), (0, 1));
+        assert_eq!((results[1].offset, results[1].len), (1, 1));
+        assert!(matches!(
+            &results[1].result,
+            Err(MixpanelError::Validation(violations)) if violations.len() == 1
+        ));
+        assert_eq!((results[2].offset, results[2].len), (2, 1));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_old_events_are_imported_with_credentials() {
+        let transport = RecordingTransport::new();
+        transport.push_json(
+            200,
+            json!({ "code": 200, "num_records_imported": 1, "status": "OK" }),
+        );
+        let old = chrono::Utc::now() - chrono::Duration::days(6);
+        let results = mixpanel(&transport)
+            .track_batch(vec![
+                Event::new("offline").time(old),
+                Event::new("online"),
+                Event::new("online"),
+            ])
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].offset, results[0].len), (0, 1));
+        assert_eq!((results[1].offset, results[1].len), (1, 2));
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url,
+            "https://api.mixpanel.com/import?strict=1&project_id=42"
+        );
+        assert_eq!(requests[0].headers["authorization"], "Basic c2E6c2VjcmV0");
+        assert_eq!(requests[1].url, "https://api.mixpanel.com/track?verbose=1");
+    }
+
+    #[tokio::test]
+    async fn test_identify_gets_defaults_and_merge_is_imported() {
+        let transport = RecordingTransport::new();
+        let mixpanel = mixpanel(&transport);
+
+        mixpanel.identify("anon", "user").await.unwrap();
+        transport.push_json(
+            200,
+            json!({ "code": 200, "num_records_imported": 1, "status": "OK" }),
+        );
+        mixpanel.merge("a", "b").await.unwrap();
+
+        let requests = transport.requests();
+        let identify = &requests[0].json().unwrap()[0];
+        assert_eq!(identify["event"], json!("$identify"));
+        assert_eq!(identify["properties"]["$anon_id"], json!("anon"));
+        assert_eq!(identify["properties"]["distinct_id"], json!("user"));
+        assert!(identify["properties"]["time"].is_i64());
+        assert!(identify["properties"]["$insert_id"].is_string());
+        assert!(requests[1]
+            .url
+            .starts_with("https://api.mixpanel.com/import"));
+        let merge = &requests[1].json().unwrap()[0];
+        assert_eq!(merge["event"], json!("$merge"));
+        assert_eq!(merge["properties"]["$distinct_ids"], json!(["a", "b"]));
+    }
+
+    #[tokio::test]
+    async fn test_simplified_id_merge() {
+        let transport = RecordingTransport::new();
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .id_merge(IdMerge::Simplified)
+            .build()
+            .unwrap();
+        let mixpanel = Mixpanel::init("token", Some(config));
+
+        mixpanel
+            .track_event(Event::new("opened").device_id("d1"))
+            .await
+            .unwrap();
+        mixpanel.identify("d1", "user").await.unwrap();
+
+        let bodies = transport.json_bodies();
+        assert_eq!(
+            bodies[0][0]["properties"]["distinct_id"],
+            json!("$device:d1")
+        );
+        let identify = &bodies[1][0]["properties"];
+        assert_eq!(identify["$device_id"], json!("d1"));
+        assert_eq!(identify["$user_id"], json!("user"));
+        assert_eq!(identify["distinct_id"], json!("user"));
+        assert!(matches!(
+            mixpanel.merge("a", "b").await,
+            Err(MixpanelError::InvalidConfig(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_super_properties_and_timers() {
+        let transport = RecordingTransport::new();
+        let mixpanel = mixpanel(&transport);
+        mixpanel.register(json!({ "source": "server", "device": "web" }));
+        mixpanel.register_once(json!({ "source": "ignored", "app": "yral" }));
+        mixpanel.time_event("upload", "user");
+
+        mixpanel
+            .track(
+                "upload",
+                Some(json!({ "distinct_id": "user", "device": "app" })),
+            )
+            .await
+            .unwrap();
+        mixpanel.unregister("app");
+        mixpanel
+            .track("upload", Some(json!({ "distinct_id": "user" })))
+            .await
+            .unwrap();
+
+        let bodies = transport.json_bodies();
+        let first = &bodies[0][0]["properties"];
+        assert_eq!(first["source"], json!("server"));
+        assert_eq!(first["app"], json!("yral"));
+        assert_eq!(first["device"], json!("app"));
+        assert!(first["$duration"].is_f64());
+        let second = &bodies[1][0]["properties"];
+        assert!(second.get("app").is_none());
+        assert!(second.get("$duration").is_none());
+    }
+
+    #[test]
+    fn test_timers_are_capped() {
+        let mixpanel = mixpanel(&RecordingTransport::new());
+        for i in 0..=MAX_TIMERS {
+            mixpanel.time_event("upload", &i.to_string());
+        }
+
+        let timers = mixpanel.timers.lock().unwrap();
+        assert_eq!(timers.len(), MAX_TIMERS);
+        assert!(timers.contains_key(&("upload".to_string(), MAX_TIMERS.to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_large_bodies_are_gzipped() {
+        let transport = RecordingTransport::new();
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .compression(Compression::Gzip { min_size: 512 })
+            .build()
+            .unwrap();
+        let mixpanel = Mixpanel::init("token", Some(config));
+
+        mixpanel.track("small", None).await.unwrap();
+        let events = (0..50).map(|_| Event::new("large")).collect();
+        mixpanel.track_batch(events).await;
+
+        let requests = transport.requests();
+        assert!(requests[0].headers.get("content-encoding").is_none());
+        assert_eq!(requests[1].headers["content-encoding"], "gzip");
+        assert_eq!(requests[1].json().unwrap().as_array().unwrap().len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_import_reports_failed_records() {
+        let transport = RecordingTransport::new();
+        transport.push_json(
+            400,
+            json!({
+                "code": 400,
+                "error": "some data points in the request failed validation",
+                "failed_records": [{
+                    "index": 0,
+                    "$insert_id": "abc",
+                    "field": "properties.time",
+                    "message": "'properties.time' is invalid"
+                }],
+                "num_records_imported": 0,
+                "status": "Bad Request"
+            }),
+        );
+        let err = mixpanel(&transport)
+            .import("old", 0, json!({ "distinct_id": "user" }))
+            .await
+            .unwrap_err();
+        match err {
+            MixpanelError::PartialFailure { imported, failed } => {
+                assert_eq!(imported, 0);
+                assert_eq!(failed[0].index, 0);
+                assert_eq!(failed[0].field.as_deref(), Some("properties.time"));
+            }
+            other => panic!("expected partial failure, got {other:?}"),
+        }
     }
 }
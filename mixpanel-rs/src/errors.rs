@@ -1,3 +1,5 @@
+use crate::types::FailedRecord;
+use crate::validation::Violation;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -5,12 +7,42 @@ pub enum MixpanelError {
     #[error("HTTP error: {0}")]
     HttpError(#[from] reqwest::Error),
 
+    #[error("Transport error: {message}")]
+    Transport { message: String, retryable: bool },
+
     #[error("Mixpanel returned error ({status}): {body}")]
     ApiError {
         status: reqwest::StatusCode,
         body: String,
     },
 
+    #[error("Mixpanel rejected the request: {0}")]
+    Rejected(String),
+
+    #[error("{} records failed to import ({imported} imported)", failed.len())]
+    PartialFailure {
+        imported: u64,
+        failed: Vec<FailedRecord>,
+    },
+
+    #[error("Event exceeds Mixpanel limits: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<Violation>),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("Invalid event: {0}")]
+    InvalidEvent(String),
+
+    #[error("Event buffer is full")]
+    BufferFull,
+
+    #[error("Event buffer has been shut down")]
+    BufferClosed,
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
     #[error("Unexpected error: {0}")]
     Other(String),
 }
@@ -0,0 +1,238 @@
+use crate::errors::MixpanelError;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// An event to be tracked, with typed setters for the properties Mixpanel
+/// gives special meaning to.
+///
+/// ```
+/// use mixpanel_rs::Event;
+///
+/// let event = Event::new("video_viewed")
+///     .distinct_id("user-1")
+///     .device_id("device-1")
+///     .os("android")
+///     .property("duration", 120);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub properties: Value,
+}
+
+impl Event {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            properties: Value::Object(Map::new()),
+        }
+    }
+
+    /// Builds an event from an untyped property object. Non-object values are
+    /// replaced by an empty object.
+    pub fn with_properties(name: &str, properties: Value) -> Self {
+        let properties = match properties {
+            Value::Object(_) => properties,
+            _ => Value::Object(Map::new()),
+        };
+        Self {
+            name: name.to_string(),
+            properties,
+        }
+    }
+
+    /// Event time, sent as milliseconds since the Unix epoch.
+    pub fn time(self, time: DateTime<Utc>) -> Self {
+        self.property("time", time.timestamp_millis())
+    }
+
+    pub fn distinct_id(self, distinct_id: &str) -> Self {
+        self.property("distinct_id", distinct_id)
+    }
+
+    pub fn device_id(self, device_id: &str) -> Self {
+        self.property("$device_id", device_id)
+    }
+
+    pub fn user_id(self, user_id: &str) -> Self {
+        self.property("$user_id", user_id)
+    }
+
+    /// IP address used for geolocation. Events carry it as `ip`; `$ip` is only
+    /// understood on profile updates.
+    pub fn ip(self, ip: &str) -> Self {
+        self.property("ip", ip)
+    }
+
+    pub fn os(self, os: &str) -> Self {
+        self.property("$os", os)
+    }
+
+    /// Deduplication key. `Mixpanel::track` generates one when it is not set.
+    pub fn insert_id(self, insert_id: &str) -> Self {
+        self.property("$insert_id", insert_id)
+    }
+
+    pub fn property(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.properties[key] = value.into();
+        self
+    }
+
+    /// Merges the fields of any `Serialize` struct or map into the event
+    /// properties, overwriting properties that are already set.
+    pub fn properties<T: Serialize>(mut self, properties: &T) -> Result<Self, MixpanelError> {
+        match serde_json::to_value(properties)? {
+            Value::Object(map) => {
+                if let Value::Object(props) = &mut self.properties {
+                    props.extend(map);
+                }
+                Ok(self)
+            }
+            other => Err(MixpanelError::InvalidEvent(format!(
+                "properties must serialize to an object, got {other}"
+            ))),
+        }
+    }
+
+    pub(crate) fn into_body(self) -> Value {
+        json!({
+            "event": self.name,
+            "properties": self.properties,
+        })
+    }
+}
+
+/// An event type with a fixed name whose fields are its properties. Usually
+/// implemented with `#[derive(MixpanelEvent)]` from the `derive` feature and
+/// sent with `Mixpanel::track_typed`.
+///
+/// ```ignore
+/// #[derive(MixpanelEvent)]
+/// #[mixpanel(name = "video_impression")]
+/// struct VideoImpressionEventData {
+///     #[mixpanel(distinct_id)]
+///     principal: String,
+///     #[mixpanel(rename = "video_id")]
+///     post_id: String,
+///     #[mixpanel(skip)]
+///     cached: bool,
+/// }
+/// ```
+pub trait MixpanelEvent {
+    const NAME: &'static str;
+
+    fn to_event(&self) -> Result<Event, MixpanelError>;
+}
+
+/// Support for the code generated by `#[derive(MixpanelEvent)]`.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    pub type Properties = Map<String, Value>;
+
+    /// A field bound to the event time.
+    pub trait EventTime {
+        fn timestamp_millis(&self) -> Option<i64>;
+    }
+
+    impl EventTime for DateTime<Utc> {
+        fn timestamp_millis(&self) -> Option<i64> {
+            Some(DateTime::timestamp_millis(self))
+        }
+    }
+
+    impl EventTime for i64 {
+        fn timestamp_millis(&self) -> Option<i64> {
+            Some(*self)
+        }
+    }
+
+    impl<T: EventTime> EventTime for Option<T> {
+        fn timestamp_millis(&self) -> Option<i64> {
+            self.as_ref().and_then(EventTime::timestamp_millis)
+        }
+    }
+
+    /// Sets `key` unless `value` serializes to null.
+    pub fn insert<T: Serialize + ?Sized>(
+        properties: &mut Properties,
+        key: &str,
+        value: &T,
+    ) -> Result<(), MixpanelError> {
+        let value = serde_json::to_value(value)?;
+        if !value.is_null() {
+            properties.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+
+    pub fn flatten<T: Serialize + ?Sized>(
+        properties: &mut Properties,
+        value: &T,
+    ) -> Result<(), MixpanelError> {
+        match serde_json::to_value(value)? {
+            Value::Object(map) => {
+                properties.extend(map);
+                Ok(())
+            }
+            Value::Null => Ok(()),
+            other => Err(MixpanelError::InvalidEvent(format!(
+                "flattened fields must serialize to an object, got {other}"
+            ))),
+        }
+    }
+
+    pub fn insert_time<T: EventTime + ?Sized>(properties: &mut Properties, value: &T) {
+        if let Some(millis) = value.timestamp_millis() {
+            properties.insert("time".to_string(), millis.into());
+        }
+    }
+
+    pub fn into_event(name: &str, properties: Properties) -> Event {
+        Event::with_properties(name, Value::Object(properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct VideoViewed {
+        video_id: String,
+        duration: u32,
+    }
+
+    #[test]
+    fn test_reserved_setters_use_mixpanel_keys() {
+        let time = DateTime::from_timestamp_millis(1_700_000_000_123).unwrap();
+        let event = Event::new("video_viewed")
+            .time(time)
+            .distinct_id("user")
+            .device_id("device")
+            .user_id("user")
+            .insert_id("abc");
+        assert_eq!(event.properties["time"], json!(1_700_000_000_123i64));
+        assert_eq!(event.properties["$device_id"], json!("device"));
+        assert_eq!(event.properties["$user_id"], json!("user"));
+        assert_eq!(event.properties["$insert_id"], json!("abc"));
+    }
+
+    #[test]
+    fn test_serializable_properties_are_merged() {
+        let event = Event::new("video_viewed")
+            .distinct_id("user")
+            .properties(&VideoViewed {
+                video_id: "v1".into(),
+                duration: 12,
+            })
+            .unwrap();
+        assert_eq!(event.properties["distinct_id"], json!("user"));
+        assert_eq!(event.properties["video_id"], json!("v1"));
+        assert_eq!(event.properties["duration"], json!(12));
+
+        assert!(Event::new("bad").properties(&vec![1, 2]).is_err());
+    }
+}
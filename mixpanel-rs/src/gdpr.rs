@@ -0,0 +1,229 @@
+use crate::errors::MixpanelError;
+use crate::transport::HttpRequest;
+use crate::types::Config;
+use crate::utils::{json_request, require_credentials, send_with_retry};
+use reqwest::header::AUTHORIZATION;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Data deletion and retrieval requests under GDPR or CCPA. Tasks run
+/// asynchronously in Mixpanel; poll their status until `is_finished`.
+#[derive(Clone)]
+pub struct MixpanelGdpr {
+    token: String,
+    config: Arc<Config>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum ComplianceType {
+    #[default]
+    #[serde(rename = "GDPR")]
+    Gdpr,
+    #[serde(rename = "CCPA")]
+    Ccpa,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TaskStatus {
+    Pending,
+    Staging,
+    Started,
+    Success,
+    Failure,
+    Revoked,
+    NotFound,
+    #[serde(other)]
+    Unknown,
+}
+
+impl TaskStatus {
+    /// Whether the task has stopped and its status will not change again.
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            TaskStatus::Success | TaskStatus::Failure | TaskStatus::Revoked | TaskStatus::NotFound
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Task {
+    pub status: TaskStatus,
+    /// For finished retrievals, where to download the exported data.
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub distinct_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    results: T,
+}
+
+#[derive(Deserialize)]
+struct CreatedTask {
+    task_id: String,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Deletion,
+    Retrieval,
+}
+
+impl Kind {
+    fn path(self) -> &'static str {
+        match self {
+            Kind::Deletion => "/app/data-deletions/v3.0/",
+            Kind::Retrieval => "/app/data-retrievals/v3.0/",
+        }
+    }
+}
+
+impl MixpanelGdpr {
+    pub fn new(token: &str, config: Arc<Config>) -> Self {
+        Self {
+            token: token.to_string(),
+            config,
+        }
+    }
+
+    /// Starts deleting every event and profile of `distinct_ids`. Returns
+    /// the task id.
+    pub async fn create_deletion(
+        &self,
+        distinct_ids: &[&str],
+        compliance_type: ComplianceType,
+    ) -> Result<String, MixpanelError> {
+        self.create(Kind::Deletion, distinct_ids, compliance_type)
+            .await
+    }
+
+    pub async fn deletion_status(&self, task_id: &str) -> Result<Task, MixpanelError> {
+        self.status(Kind::Deletion, task_id).await
+    }
+
+    /// Cancels a deletion that has not started yet.
+    pub async fn cancel_deletion(&self, task_id: &str) -> Result<(), MixpanelError> {
+        let mut request = self.request(Kind::Deletion, task_id)?;
+        request.method = reqwest::Method::DELETE;
+        send_with_retry(&self.config, request).await?;
+        Ok(())
+    }
+
+    /// Starts exporting the data held on `distinct_ids`. Returns the task
+    /// id.
+    pub async fn create_retrieval(
+        &self,
+        distinct_ids: &[&str],
+        compliance_type: ComplianceType,
+    ) -> Result<String, MixpanelError> {
+        self.create(Kind::Retrieval, distinct_ids, compliance_type)
+            .await
+    }
+
+    pub async fn retrieval_status(&self, task_id: &str) -> Result<Task, MixpanelError> {
+        self.status(Kind::Retrieval, task_id).await
+    }
+
+    async fn create(
+        &self,
+        kind: Kind,
+        distinct_ids: &[&str],
+        compliance_type: ComplianceType,
+    ) -> Result<String, MixpanelError> {
+        let credentials = require_credentials(&self.config, "the GDPR API")?;
+        let url = self.url(kind, "");
+        let body = json!({
+            "distinct_ids": distinct_ids,
+            "compliance_type": compliance_type,
+        });
+        let request =
+            json_request(&url, &body)?.header(AUTHORIZATION, &credentials.authorization());
+        let task: CreatedTask = parse(&send_with_retry(&self.config, request).await?)?;
+        Ok(task.task_id)
+    }
+
+    async fn status(&self, kind: Kind, task_id: &str) -> Result<Task, MixpanelError> {
+        let request = self.request(kind, task_id)?;
+        parse(&send_with_retry(&self.config, request).await?)
+    }
+
+    /// An authenticated `GET` for task `task_id`.
+    fn request(&self, kind: Kind, task_id: &str) -> Result<HttpRequest, MixpanelError> {
+        let credentials = require_credentials(&self.config, "the GDPR API")?;
+        Ok(HttpRequest::get(&self.url(kind, task_id))
+            .header(AUTHORIZATION, &credentials.authorization()))
+    }
+
+    fn url(&self, kind: Kind, task_id: &str) -> String {
+        self.config
+            .query_url(&format!("{}{task_id}?token={}", kind.path(), self.token))
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, MixpanelError> {
+    let envelope: Envelope<T> = serde_json::from_str(body)?;
+    Ok(envelope.results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::Credentials;
+
+    fn gdpr(transport: &RecordingTransport) -> MixpanelGdpr {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .build()
+            .unwrap();
+        MixpanelGdpr::new("token", Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_create_deletion_and_poll() {
+        let transport = RecordingTransport::new();
+        transport
+            .push_json(200, json!({ "status": "ok", "results": { "task_id": "t1" } }))
+            .push_json(
+                200,
+                json!({ "status": "ok", "results": { "status": "SUCCESS", "distinct_ids": ["u1"] } }),
+            );
+        let gdpr = gdpr(&transport);
+
+        let task_id = gdpr
+            .create_deletion(&["u1"], ComplianceType::Gdpr)
+            .await
+            .unwrap();
+        let task = gdpr.deletion_status(&task_id).await.unwrap();
+
+        assert_eq!(task_id, "t1");
+        assert!(task.status.is_finished());
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url,
+            "https://mixpanel.com/api/app/data-deletions/v3.0/?token=token"
+        );
+        assert_eq!(
+            requests[0].json().unwrap(),
+            json!({ "distinct_ids": ["u1"], "compliance_type": "GDPR" })
+        );
+        assert_eq!(
+            requests[1].url,
+            "https://mixpanel.com/api/app/data-deletions/v3.0/t1?token=token"
+        );
+    }
+
+    #[test]
+    fn test_unknown_status_is_tolerated() {
+        let task: Task = serde_json::from_str(r#"{ "status": "SOMETHING_NEW" }"#).unwrap();
+        assert_eq!(task.status, TaskStatus::Unknown);
+        assert!(!task.status.is_finished());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::types::Config;
+use crate::types::{BatchResult, Config, TrackResponse};
 use crate::{errors::MixpanelError, profile_helpers::ProfileHelpers};
 use serde_json::{json, Value};
 use std::sync::Arc;
@@ -20,7 +20,7 @@ impl MixpanelGroups {
         group_key: &str,
         group_id: &str,
         properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<TrackResponse, MixpanelError> {
         self.helper
             .send(json!({
                 "$group_key": group_key,
@@ -35,7 +35,7 @@ impl MixpanelGroups {
         group_key: &str,
         group_id: &str,
         properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<TrackResponse, MixpanelError> {
         self.helper
             .send(json!({
                 "$group_key": group_key,
@@ -44,4 +44,94 @@ impl MixpanelGroups {
             }))
             .await
     }
+
+    /// Removes the given properties from the group profile. `properties` is
+    /// a list of property names.
+    pub async fn unset(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$group_key": group_key,
+                "$group_id": group_id,
+                "$unset": properties
+            }))
+            .await
+    }
+
+    /// Removes a value from each of the given list properties.
+    pub async fn remove(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$group_key": group_key,
+                "$group_id": group_id,
+                "$remove": properties
+            }))
+            .await
+    }
+
+    /// Merges the given values into list properties, skipping values that are
+    /// already present.
+    pub async fn union(
+        &self,
+        group_key: &str,
+        group_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$group_key": group_key,
+                "$group_id": group_id,
+                "$union": properties
+            }))
+            .await
+    }
+
+    pub async fn delete_group(
+        &self,
+        group_key: &str,
+        group_id: &str,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$group_key": group_key,
+                "$group_id": group_id,
+                "$delete": ""
+            }))
+            .await
+    }
+
+    /// Sets properties on many groups of the same `group_key` at once, given
+    /// as `(group_id, properties)` pairs.
+    pub async fn set_batch(
+        &self,
+        group_key: &str,
+        groups: Vec<(String, Value)>,
+    ) -> Vec<BatchResult> {
+        let updates = groups
+            .into_iter()
+            .map(|(group_id, properties)| {
+                json!({
+                    "$group_key": group_key,
+                    "$group_id": group_id,
+                    "$set": properties
+                })
+            })
+            .collect();
+        self.helper.send_batch(updates).await
+    }
+
+    /// Sends many group profile updates in as few requests as possible. Each
+    /// update must already carry its `$group_key` and `$group_id`.
+    pub async fn batch(&self, updates: Vec<Value>) -> Vec<BatchResult> {
+        self.helper.send_batch(updates).await
+    }
 }
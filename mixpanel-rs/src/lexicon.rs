@@ -0,0 +1,266 @@
+use crate::errors::MixpanelError;
+use crate::types::Config;
+use crate::utils::{json_request, require_credentials, send_with_retry};
+use reqwest::header::AUTHORIZATION;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+/// Uploads Lexicon schemas, the descriptions of events and profile
+/// properties shown in Mixpanel's data dictionary. Needs service account
+/// credentials.
+#[derive(Clone)]
+pub struct MixpanelLexicon {
+    config: Arc<Config>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityType {
+    Event,
+    Profile,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    String,
+    Number,
+    Boolean,
+    List,
+    Object,
+    DateTime,
+}
+
+/// A property of an event or profile schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertySchema {
+    pub name: String,
+    pub property_type: PropertyType,
+    pub description: Option<String>,
+}
+
+impl PropertySchema {
+    pub fn new(name: &str, property_type: PropertyType) -> Self {
+        Self {
+            name: name.to_string(),
+            property_type,
+            description: None,
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    fn json_schema(&self) -> Value {
+        let mut schema = match self.property_type {
+            PropertyType::String => json!({ "type": "string" }),
+            PropertyType::Number => json!({ "type": "number" }),
+            PropertyType::Boolean => json!({ "type": "boolean" }),
+            PropertyType::List => json!({ "type": "array" }),
+            PropertyType::Object => json!({ "type": "object" }),
+            PropertyType::DateTime => json!({ "type": "string", "format": "date-time" }),
+        };
+        if let Some(description) = &self.description {
+            schema["description"] = json!(description);
+        }
+        schema
+    }
+}
+
+/// The Lexicon entry for one event, or for profiles when `entity_type` is
+/// `Profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub entity_type: EntityType,
+    pub name: String,
+    pub description: Option<String>,
+    pub properties: Vec<PropertySchema>,
+    pub hidden: bool,
+    pub tags: Vec<String>,
+}
+
+impl Schema {
+    pub fn event(name: &str) -> Self {
+        Self::new(EntityType::Event, name)
+    }
+
+    /// The schema of user profiles, which Mixpanel names `$user`.
+    pub fn profile() -> Self {
+        Self::new(EntityType::Profile, "$user")
+    }
+
+    fn new(entity_type: EntityType, name: &str) -> Self {
+        Self {
+            entity_type,
+            name: name.to_string(),
+            description: None,
+            properties: Vec::new(),
+            hidden: false,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn property(mut self, property: PropertySchema) -> Self {
+        self.properties.push(property);
+        self
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag.to_string());
+        self
+    }
+
+    fn to_entry(&self) -> Value {
+        let properties: Map<String, Value> = self
+            .properties
+            .iter()
+            .map(|property| (property.name.clone(), property.json_schema()))
+            .collect();
+        let mut schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema",
+            "properties": properties,
+            "metadata": {
+                "com.mixpanel": { "hidden": self.hidden, "tags": self.tags }
+            }
+        });
+        if let Some(description) = &self.description {
+            schema["description"] = json!(description);
+        }
+        json!({
+            "entityType": self.entity_type,
+            "name": self.name,
+            "schemaJson": schema,
+        })
+    }
+}
+
+/// A Rust type that describes its own Lexicon entry, so the event catalog is
+/// generated from the code that sends the events. Push it with
+/// `MixpanelLexicon::upload_for`.
+pub trait LexiconSchema {
+    fn lexicon_schema() -> Schema;
+}
+
+impl MixpanelLexicon {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+
+    /// Creates or updates `schemas`. With `truncate`, every schema of the
+    /// project that is not in `schemas` is deleted.
+    pub async fn upload(&self, schemas: &[Schema], truncate: bool) -> Result<(), MixpanelError> {
+        let credentials = require_credentials(&self.config, "the Lexicon API")?;
+        let project_id = credentials.project_id().ok_or_else(|| {
+            MixpanelError::InvalidConfig("Lexicon uploads require a service account".into())
+        })?;
+        let url = self
+            .config
+            .query_url(&format!("/app/projects/{project_id}/schemas"));
+        let body = json!({
+            "truncate": truncate,
+            "entries": schemas.iter().map(Schema::to_entry).collect::<Vec<_>>(),
+        });
+        let request =
+            json_request(&url, &body)?.header(AUTHORIZATION, &credentials.authorization());
+        send_with_retry(&self.config, request).await?;
+        Ok(())
+    }
+
+    /// Creates or updates the schema `T` describes, leaving the rest of the
+    /// project's schemas alone.
+    pub async fn upload_for<T: LexiconSchema>(&self) -> Result<(), MixpanelError> {
+        self.upload(&[T::lexicon_schema()], false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::Credentials;
+
+    fn lexicon(transport: &RecordingTransport) -> MixpanelLexicon {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .build()
+            .unwrap();
+        MixpanelLexicon::new(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_upload_sends_json_schemas() {
+        let transport = RecordingTransport::new();
+        let schema = Schema::event("video_viewed")
+            .description("A video was watched")
+            .property(
+                PropertySchema::new("video_id", PropertyType::String).description("Canister id"),
+            )
+            .property(PropertySchema::new("watched_at", PropertyType::DateTime))
+            .tag("video");
+
+        lexicon(&transport).upload(&[schema], false).await.unwrap();
+
+        let request = &transport.requests()[0];
+        assert_eq!(
+            request.url,
+            "https://mixpanel.com/api/app/projects/42/schemas"
+        );
+        let body = request.json().unwrap();
+        let entry = &body["entries"][0];
+        assert_eq!(body["truncate"], json!(false));
+        assert_eq!(entry["entityType"], json!("event"));
+        assert_eq!(entry["name"], json!("video_viewed"));
+        assert_eq!(
+            entry["schemaJson"]["properties"]["video_id"],
+            json!({ "type": "string", "description": "Canister id" })
+        );
+        assert_eq!(
+            entry["schemaJson"]["properties"]["watched_at"]["format"],
+            json!("date-time")
+        );
+        assert_eq!(
+            entry["schemaJson"]["metadata"]["com.mixpanel"]["tags"],
+            json!(["video"])
+        );
+    }
+
+    struct SignupCompleted;
+
+    impl LexiconSchema for SignupCompleted {
+        fn lexicon_schema() -> Schema {
+            Schema::event("signup_completed")
+                .property(PropertySchema::new("method", PropertyType::String))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_for_sends_the_type_schema() {
+        let transport = RecordingTransport::new();
+
+        lexicon(&transport)
+            .upload_for::<SignupCompleted>()
+            .await
+            .unwrap();
+
+        let body = transport.requests()[0].json().unwrap();
+        assert_eq!(body["truncate"], json!(false));
+        assert_eq!(body["entries"][0]["name"], json!("signup_completed"));
+        assert_eq!(
+            body["entries"][0]["schemaJson"]["properties"]["method"],
+            json!({ "type": "string" })
+        );
+    }
+}
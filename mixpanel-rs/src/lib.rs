@@ -1,9 +1,23 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod buffered;
 pub mod client;
 pub mod errors;
+pub mod event;
+pub mod gdpr;
 pub mod groups;
+pub mod lexicon;
+pub mod lookup_tables;
 pub mod people;
 pub mod profile_helpers;
+pub mod query;
+pub mod transport;
 pub mod types;
 pub mod utils;
+pub mod validation;
 
+pub use buffered::BufferedMixpanel;
 pub use client::Mixpanel;
+pub use event::{Event, MixpanelEvent};
+#[cfg(feature = "derive")]
+pub use mixpanel_derive::MixpanelEvent;
@@ -0,0 +1,175 @@
+use crate::errors::MixpanelError;
+use crate::transport::HttpRequest;
+use crate::types::Config;
+use crate::utils::{require_credentials, send_with_retry};
+use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::Method;
+use serde::Deserialize;
+use std::sync::Arc;
+use url::form_urlencoded;
+
+/// Lists and replaces Lookup Tables, which join extra columns onto an event
+/// or profile property by its value. Needs service account credentials.
+///
+/// Mixpanel's API cannot create lookup tables: create each table and map it
+/// to its property in the Mixpanel UI first, then keep its contents up to
+/// date with `replace`.
+#[derive(Clone)]
+pub struct MixpanelLookupTables {
+    config: Arc<Config>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LookupTable {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+struct ListResponse {
+    results: Vec<LookupTable>,
+}
+
+impl MixpanelLookupTables {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+
+    pub async fn list(&self) -> Result<Vec<LookupTable>, MixpanelError> {
+        let request = self.request(Method::GET, "")?;
+        let body = send_with_retry(&self.config, request).await?;
+        let response: ListResponse = serde_json::from_str(&body)?;
+        Ok(response.results)
+    }
+
+    /// Replaces the contents of lookup table `table_id` with `csv`. The first
+    /// line holds the column names; the first column is the join key.
+    pub async fn replace_csv(&self, table_id: &str, csv: String) -> Result<(), MixpanelError> {
+        let mut request = self.request(Method::PUT, &format!("/{table_id}"))?;
+        request.body = csv.into_bytes();
+        send_with_retry(&self.config, request.header(CONTENT_TYPE, "text/csv")).await?;
+        Ok(())
+    }
+
+    /// Replaces the contents of lookup table `table_id` with `rows`, each of
+    /// which needs a value for every column in `header`.
+    pub async fn replace<I, R, S>(
+        &self,
+        table_id: &str,
+        header: &[&str],
+        rows: I,
+    ) -> Result<(), MixpanelError>
+    where
+        I: IntoIterator<Item = R>,
+        R: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.replace_csv(table_id, to_csv(header, rows)?).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<HttpRequest, MixpanelError> {
+        let credentials = require_credentials(&self.config, "the lookup tables API")?;
+        let project_id = credentials.project_id().ok_or_else(|| {
+            MixpanelError::InvalidConfig("lookup tables require a service account".into())
+        })?;
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("project_id", project_id)
+            .finish();
+        let url = self
+            .config
+            .api_url(&format!("/lookup-tables{path}?{query}"));
+        let request = HttpRequest {
+            method,
+            url,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        };
+        Ok(request.header(AUTHORIZATION, &credentials.authorization()))
+    }
+}
+
+/// Writes `header` and `rows` as RFC 4180 CSV.
+pub fn to_csv<I, R, S>(header: &[&str], rows: I) -> Result<String, MixpanelError>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut csv = String::new();
+    write_row(&mut csv, header.iter());
+    for (i, row) in rows.into_iter().enumerate() {
+        let row: Vec<S> = row.into_iter().collect();
+        if row.len() != header.len() {
+            return Err(MixpanelError::Other(format!(
+                "lookup table row {i} has {} columns, expected {}",
+                row.len(),
+                header.len()
+            )));
+        }
+        write_row(&mut csv, row.iter());
+    }
+    Ok(csv)
+}
+
+fn write_row<S: AsRef<str>>(csv: &mut String, fields: impl Iterator<Item = S>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            csv.push(',');
+        }
+        let field = field.as_ref();
+        if field.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&field.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(field);
+        }
+    }
+    csv.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use crate::types::Credentials;
+
+    #[test]
+    fn test_to_csv_quotes_fields() {
+        let csv = to_csv(
+            &["video_id", "title"],
+            vec![vec!["v1", "Cats, dogs"], vec!["v2", "The \"best\" one"]],
+        )
+        .unwrap();
+        assert_eq!(
+            csv,
+            "video_id,title\nv1,\"Cats, dogs\"\nv2,\"The \"\"best\"\" one\"\n"
+        );
+        assert!(to_csv(&["a", "b"], vec![vec!["only one"]]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replace_puts_csv() {
+        let transport = RecordingTransport::new();
+        transport.push_response(200, r#"{"code":200,"status":"OK"}"#);
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .build()
+            .unwrap();
+
+        MixpanelLookupTables::new(Arc::new(config))
+            .replace("table-1", &["id", "name"], [["c1", "Creator"]])
+            .await
+            .unwrap();
+
+        let request = &transport.requests()[0];
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(
+            request.url,
+            "https://api.mixpanel.com/lookup-tables/table-1?project_id=42"
+        );
+        assert_eq!(request.headers[CONTENT_TYPE], "text/csv");
+        assert_eq!(request.body, b"id,name\nc1,Creator\n");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::types::Config;
+use crate::types::{BatchResult, Config, TrackResponse};
 use crate::{errors::MixpanelError, profile_helpers::ProfileHelpers};
 use serde_json::{json, Value};
 use std::sync::Arc;
@@ -11,7 +11,7 @@ pub struct MixpanelPeople {
 impl MixpanelPeople {
     pub fn new(token: &str, config: Arc<Config>) -> Self {
         Self {
-            helper: ProfileHelpers::new(token, config, "/engage#profile-set"),
+            helper: ProfileHelpers::new(token, config, "/engage"),
         }
     }
 
@@ -20,7 +20,7 @@ impl MixpanelPeople {
         distinct_id: &str,
         ip: Value,
         properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<TrackResponse, MixpanelError> {
         self.helper
             .send(json!({
                 "$distinct_id": distinct_id,
@@ -34,7 +34,7 @@ impl MixpanelPeople {
         &self,
         distinct_id: &str,
         properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<TrackResponse, MixpanelError> {
         self.helper
             .send(json!({
                 "$distinct_id": distinct_id,
@@ -47,7 +47,7 @@ impl MixpanelPeople {
         &self,
         distinct_id: &str,
         properties: Value,
-    ) -> Result<Value, MixpanelError> {
+    ) -> Result<TrackResponse, MixpanelError> {
         self.helper
             .send(json!({
                 "$distinct_id": distinct_id,
@@ -55,4 +55,117 @@ impl MixpanelPeople {
             }))
             .await
     }
+
+    /// Sets properties only if they are not already set on the profile, e.g.
+    /// first-seen attributes.
+    pub async fn set_once(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$set_once": properties
+            }))
+            .await
+    }
+
+    /// Removes the given properties from the profile. `properties` is a list
+    /// of property names.
+    pub async fn unset(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$unset": properties
+            }))
+            .await
+    }
+
+    /// Removes a value from each of the given list properties.
+    pub async fn remove(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$remove": properties
+            }))
+            .await
+    }
+
+    /// Merges the given values into list properties, skipping values that are
+    /// already present.
+    pub async fn union(
+        &self,
+        distinct_id: &str,
+        properties: Value,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$union": properties
+            }))
+            .await
+    }
+
+    /// Deletes the profile. With `ignore_alias` set, only the profile with
+    /// this exact distinct id is deleted, not the ones aliased to it.
+    pub async fn delete_user(
+        &self,
+        distinct_id: &str,
+        ignore_alias: bool,
+    ) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$delete": "",
+                "$ignore_alias": ignore_alias
+            }))
+            .await
+    }
+
+    /// Records a revenue transaction of `amount` on the profile. Extra
+    /// `properties` are stored alongside the transaction.
+    pub async fn track_charge(
+        &self,
+        distinct_id: &str,
+        amount: f64,
+        properties: Option<Value>,
+    ) -> Result<TrackResponse, MixpanelError> {
+        let mut transaction = properties.unwrap_or_else(|| json!({}));
+        transaction["$amount"] = json!(amount);
+        if transaction.get("$time").is_none() {
+            transaction["$time"] =
+                json!(chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$append": { "$transactions": transaction }
+            }))
+            .await
+    }
+
+    /// Clears all transactions recorded with `track_charge`.
+    pub async fn clear_charges(&self, distinct_id: &str) -> Result<TrackResponse, MixpanelError> {
+        self.helper
+            .send(json!({
+                "$distinct_id": distinct_id,
+                "$set": { "$transactions": [] }
+            }))
+            .await
+    }
+
+    /// Sends many profile updates (`$set`, `$add`, ...) in as few requests as
+    /// possible. Each update must already carry its `$distinct_id`.
+    pub async fn batch(&self, updates: Vec<Value>) -> Vec<BatchResult> {
+        self.helper.send_batch(updates).await
+    }
 }
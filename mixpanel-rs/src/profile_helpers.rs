@@ -1,4 +1,5 @@
-use crate::utils::send_request;
+use crate::types::{BatchResult, TrackResponse};
+use crate::utils::{send_batch_request, send_request};
 use crate::{errors::MixpanelError, types::Config};
 use serde_json::{json, Value};
 use std::sync::Arc;
@@ -19,8 +20,19 @@ impl ProfileHelpers {
         }
     }
 
-    pub async fn send(&self, mut data: Value) -> Result<Value, MixpanelError> {
+    pub async fn send(&self, mut data: Value) -> Result<TrackResponse, MixpanelError> {
         data["$token"] = json!(self.token);
         send_request(&self.config, &self.endpoint, data).await
     }
+
+    pub async fn send_batch(&self, updates: Vec<Value>) -> Vec<BatchResult> {
+        let updates = updates
+            .into_iter()
+            .map(|mut data| {
+                data["$token"] = json!(self.token);
+                data
+            })
+            .collect();
+        send_batch_request(&self.config, &self.endpoint, updates).await
+    }
 }
@@ -0,0 +1,784 @@
+use crate::errors::MixpanelError;
+use crate::transport::{BodyStream, HttpRequest};
+use crate::types::{Config, Credentials};
+use crate::utils::{require_credentials, send_streaming_with_retry, send_with_retry};
+use chrono::NaiveDate;
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use url::form_urlencoded;
+
+/// Read access to a project: the Raw Event Export and Query APIs.
+///
+/// Every call needs `Config::credentials`; service accounts also pick the
+/// project that is queried.
+#[derive(Clone)]
+pub struct MixpanelQuery {
+    config: Arc<Config>,
+}
+
+/// Granularity of time series in query results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Unit {
+    Minute,
+    Hour,
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Unit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Unit::Minute => "minute",
+            Unit::Hour => "hour",
+            Unit::Day => "day",
+            Unit::Week => "week",
+            Unit::Month => "month",
+        }
+    }
+}
+
+/// Which events the raw export returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportQuery {
+    pub from_date: NaiveDate,
+    pub to_date: NaiveDate,
+    /// Only export these events; all events when empty.
+    pub events: Vec<String>,
+    /// A Mixpanel segmentation expression, e.g. `properties["$os"] == "iOS"`.
+    pub filter: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl ExportQuery {
+    pub fn new(from_date: NaiveDate, to_date: NaiveDate) -> Self {
+        Self {
+            from_date,
+            to_date,
+            events: Vec::new(),
+            filter: None,
+            limit: None,
+        }
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.events.push(event.to_string());
+        self
+    }
+
+    pub fn filter(mut self, expression: &str) -> Self {
+        self.filter = Some(expression.to_string());
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+/// An event as returned by the raw export.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExportedEvent {
+    pub event: String,
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+}
+
+impl ExportedEvent {
+    pub fn distinct_id(&self) -> Option<&str> {
+        self.properties.get("distinct_id")?.as_str()
+    }
+
+    /// Event time in seconds since the epoch.
+    pub fn time(&self) -> Option<i64> {
+        self.properties.get("time")?.as_i64()
+    }
+
+    pub fn insert_id(&self) -> Option<&str> {
+        self.properties.get("$insert_id")?.as_str()
+    }
+}
+
+/// Events of a raw export, parsed as they arrive so large date ranges never
+/// have to fit in memory.
+pub struct ExportStream {
+    body: Box<dyn BodyStream>,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl ExportStream {
+    fn new(body: Box<dyn BodyStream>) -> Self {
+        Self {
+            body,
+            buffer: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// The next exported event, or `None` once the export is complete.
+    pub async fn next(&mut self) -> Option<Result<ExportedEvent, MixpanelError>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                match parse_line(&line) {
+                    Some(event) => return Some(event),
+                    None => continue,
+                }
+            }
+            if self.done {
+                let line = std::mem::take(&mut self.buffer);
+                return parse_line(&line);
+            }
+            match self.body.next_chunk().await {
+                Ok(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    self.buffer.clear();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Reads the remaining events into memory.
+    pub async fn collect(mut self) -> Result<Vec<ExportedEvent>, MixpanelError> {
+        let mut events = Vec::new();
+        while let Some(event) = self.next().await {
+            events.push(event?);
+        }
+        Ok(events)
+    }
+}
+
+fn parse_line(line: &[u8]) -> Option<Result<ExportedEvent, MixpanelError>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    Some(serde_json::from_slice(line).map_err(Into::into))
+}
+
+/// How segmentation counts events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CountType {
+    #[default]
+    General,
+    Unique,
+    Average,
+}
+
+impl CountType {
+    fn as_str(self) -> &'static str {
+        match self {
+            CountType::General => "general",
+            CountType::Unique => "unique",
+            CountType::Average => "average",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentationQuery {
+    pub event: String,
+    pub from_date: NaiveDate,
+    pub to_date: NaiveDate,
+    pub unit: Unit,
+    pub count_type: CountType,
+    /// Property expression to segment by, e.g. `properties["$os"]`.
+    pub on: Option<String>,
+    pub filter: Option<String>,
+}
+
+impl SegmentationQuery {
+    pub fn new(event: &str, from_date: NaiveDate, to_date: NaiveDate) -> Self {
+        Self {
+            event: event.to_string(),
+            from_date,
+            to_date,
+            unit: Unit::default(),
+            count_type: CountType::default(),
+            on: None,
+            filter: None,
+        }
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn count_type(mut self, count_type: CountType) -> Self {
+        self.count_type = count_type;
+        self
+    }
+
+    pub fn on(mut self, expression: &str) -> Self {
+        self.on = Some(expression.to_string());
+        self
+    }
+
+    pub fn filter(mut self, expression: &str) -> Self {
+        self.filter = Some(expression.to_string());
+        self
+    }
+}
+
+/// Counts per segment and date. Without `on`, the only segment is the event
+/// name.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Segmentation {
+    pub series: Vec<String>,
+    pub values: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+impl Segmentation {
+    /// Sum of a segment over all dates.
+    pub fn total(&self, segment: &str) -> f64 {
+        self.values
+            .get(segment)
+            .map(|counts| counts.values().sum())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+struct SegmentationResponse {
+    data: Segmentation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DateRange {
+    pub from_date: String,
+    pub to_date: String,
+}
+
+/// Result of a saved Insights report. The shape of `series` depends on how
+/// the report is built, so it is left as JSON.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct InsightsReport {
+    #[serde(default)]
+    pub computed_at: Option<String>,
+    #[serde(default)]
+    pub date_range: Option<DateRange>,
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub series: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunnelQuery {
+    pub funnel_id: u64,
+    pub from_date: NaiveDate,
+    pub to_date: NaiveDate,
+    pub unit: Option<Unit>,
+}
+
+impl FunnelQuery {
+    pub fn new(funnel_id: u64, from_date: NaiveDate, to_date: NaiveDate) -> Self {
+        Self {
+            funnel_id,
+            from_date,
+            to_date,
+            unit: None,
+        }
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FunnelStep {
+    pub count: u64,
+    #[serde(default)]
+    pub step_conv_ratio: f64,
+    #[serde(default)]
+    pub overall_conv_ratio: f64,
+    /// Average seconds from the previous step.
+    #[serde(default)]
+    pub avg_time: Option<f64>,
+    pub event: String,
+    #[serde(default)]
+    pub goal: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FunnelPeriod {
+    pub steps: Vec<FunnelStep>,
+}
+
+/// Funnel steps for every date in `dates`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunnelReport {
+    pub dates: Vec<String>,
+    pub periods: BTreeMap<String, FunnelPeriod>,
+}
+
+#[derive(Deserialize)]
+struct FunnelResponse {
+    meta: FunnelMeta,
+    data: BTreeMap<String, FunnelPeriod>,
+}
+
+#[derive(Deserialize)]
+struct FunnelMeta {
+    dates: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionQuery {
+    pub from_date: NaiveDate,
+    pub to_date: NaiveDate,
+    /// Event that puts a user in a cohort; any event when `None`.
+    pub born_event: Option<String>,
+    /// Event that counts as coming back; any event when `None`.
+    pub event: Option<String>,
+    pub unit: Unit,
+    pub interval_count: Option<u32>,
+}
+
+impl RetentionQuery {
+    pub fn new(from_date: NaiveDate, to_date: NaiveDate) -> Self {
+        Self {
+            from_date,
+            to_date,
+            born_event: None,
+            event: None,
+            unit: Unit::default(),
+            interval_count: None,
+        }
+    }
+
+    pub fn born_event(mut self, event: &str) -> Self {
+        self.born_event = Some(event.to_string());
+        self
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub fn interval_count(mut self, count: u32) -> Self {
+        self.interval_count = Some(count);
+        self
+    }
+}
+
+/// Users born on one date and how many of them came back in each following
+/// interval.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RetentionCohort {
+    pub first: u64,
+    pub counts: Vec<u64>,
+}
+
+/// Which profiles an Engage query returns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngageQuery {
+    pub filter: Option<String>,
+    pub distinct_ids: Vec<String>,
+    /// Only return these properties; all of them when empty.
+    pub output_properties: Vec<String>,
+}
+
+impl EngageQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(mut self, expression: &str) -> Self {
+        self.filter = Some(expression.to_string());
+        self
+    }
+
+    pub fn distinct_id(mut self, distinct_id: &str) -> Self {
+        self.distinct_ids.push(distinct_id.to_string());
+        self
+    }
+
+    pub fn output_property(mut self, property: &str) -> Self {
+        self.output_properties.push(property.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Profile {
+    #[serde(rename = "$distinct_id")]
+    pub distinct_id: String,
+    #[serde(rename = "$properties", default)]
+    pub properties: Map<String, Value>,
+}
+
+/// One page of Engage results. Pass it to `MixpanelQuery::engage_page` to
+/// get the next one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EngagePage {
+    pub page: u32,
+    pub page_size: u32,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub total: u64,
+    pub results: Vec<Profile>,
+}
+
+impl EngagePage {
+    pub fn is_last(&self) -> bool {
+        self.results.is_empty() || (self.page as u64 + 1) * self.page_size as u64 >= self.total
+    }
+}
+
+impl MixpanelQuery {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+
+    /// Streams raw events matching `query`. The download is bounded by the
+    /// config's read timeout rather than its total request timeout, so large
+    /// date ranges are not cut off.
+    pub async fn export(&self, query: &ExportQuery) -> Result<ExportStream, MixpanelError> {
+        let mut params = vec![
+            ("from_date", format_date(query.from_date)),
+            ("to_date", format_date(query.to_date)),
+        ];
+        if !query.events.is_empty() {
+            params.push(("event", serde_json::to_string(&query.events)?));
+        }
+        if let Some(filter) = &query.filter {
+            params.push(("where", filter.clone()));
+        }
+        if let Some(limit) = query.limit {
+            params.push(("limit", limit.to_string()));
+        }
+        let url = self.config.export_url("/export");
+        let request = self.get(&url, params)?;
+        let response = send_streaming_with_retry(&self.config, request).await?;
+        Ok(ExportStream::new(response.body))
+    }
+
+    pub async fn segmentation(
+        &self,
+        query: &SegmentationQuery,
+    ) -> Result<Segmentation, MixpanelError> {
+        let mut params = vec![
+            ("event", query.event.clone()),
+            ("from_date", format_date(query.from_date)),
+            ("to_date", format_date(query.to_date)),
+            ("unit", query.unit.as_str().to_string()),
+            ("type", query.count_type.as_str().to_string()),
+        ];
+        if let Some(on) = &query.on {
+            params.push(("on", on.clone()));
+        }
+        if let Some(filter) = &query.filter {
+            params.push(("where", filter.clone()));
+        }
+        let response: SegmentationResponse = self.get_json("/query/segmentation", params).await?;
+        Ok(response.data)
+    }
+
+    /// Runs the saved Insights report `bookmark_id`.
+    pub async fn insights(&self, bookmark_id: u64) -> Result<InsightsReport, MixpanelError> {
+        self.get_json(
+            "/query/insights",
+            vec![("bookmark_id", bookmark_id.to_string())],
+        )
+        .await
+    }
+
+    pub async fn funnel(&self, query: &FunnelQuery) -> Result<FunnelReport, MixpanelError> {
+        let mut params = vec![
+            ("funnel_id", query.funnel_id.to_string()),
+            ("from_date", format_date(query.from_date)),
+            ("to_date", format_date(query.to_date)),
+        ];
+        if let Some(unit) = query.unit {
+            params.push(("unit", unit.as_str().to_string()));
+        }
+        let response: FunnelResponse = self.get_json("/query/funnels", params).await?;
+        Ok(FunnelReport {
+            dates: response.meta.dates,
+            periods: response.data,
+        })
+    }
+
+    /// Retention cohorts keyed by the date they were born.
+    pub async fn retention(
+        &self,
+        query: &RetentionQuery,
+    ) -> Result<BTreeMap<String, RetentionCohort>, MixpanelError> {
+        let mut params = vec![
+            ("from_date", format_date(query.from_date)),
+            ("to_date", format_date(query.to_date)),
+            ("unit", query.unit.as_str().to_string()),
+        ];
+        if let Some(event) = &query.born_event {
+            params.push(("born_event", event.clone()));
+        }
+        if let Some(event) = &query.event {
+            params.push(("event", event.clone()));
+        }
+        if let Some(count) = query.interval_count {
+            params.push(("interval_count", count.to_string()));
+        }
+        self.get_json("/query/retention", params).await
+    }
+
+    /// The first page of profiles matching `query`.
+    pub async fn engage(&self, query: &EngageQuery) -> Result<EngagePage, MixpanelError> {
+        self.engage_request(query, None).await
+    }
+
+    /// The page after `previous`, or `None` when `previous` was the last.
+    pub async fn engage_page(
+        &self,
+        query: &EngageQuery,
+        previous: &EngagePage,
+    ) -> Result<Option<EngagePage>, MixpanelError> {
+        if previous.is_last() {
+            return Ok(None);
+        }
+        self.engage_request(query, Some(previous)).await.map(Some)
+    }
+
+    /// All profiles matching `query`, fetching every page.
+    pub async fn engage_all(&self, query: &EngageQuery) -> Result<Vec<Profile>, MixpanelError> {
+        let mut page = self.engage(query).await?;
+        let mut profiles = Vec::new();
+        loop {
+            let next = self.engage_page(query, &page).await?;
+            profiles.append(&mut page.results);
+            match next {
+                Some(next) => page = next,
+                None => return Ok(profiles),
+            }
+        }
+    }
+
+    async fn engage_request(
+        &self,
+        query: &EngageQuery,
+        previous: Option<&EngagePage>,
+    ) -> Result<EngagePage, MixpanelError> {
+        let mut params = Vec::new();
+        if let Some(filter) = &query.filter {
+            params.push(("where", filter.clone()));
+        }
+        if !query.distinct_ids.is_empty() {
+            params.push(("distinct_ids", serde_json::to_string(&query.distinct_ids)?));
+        }
+        if !query.output_properties.is_empty() {
+            params.push((
+                "output_properties",
+                serde_json::to_string(&query.output_properties)?,
+            ));
+        }
+        if let Some(previous) = previous {
+            params.push(("page", (previous.page + 1).to_string()));
+            if let Some(session_id) = &previous.session_id {
+                params.push(("session_id", session_id.clone()));
+            }
+        }
+
+        let credentials = self.credentials()?;
+        let url = with_params(
+            &self.config.query_url("/query/engage"),
+            project_param(credentials),
+        );
+        let request = HttpRequest::post(&url, encode(params).into_bytes())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(ACCEPT, "application/json")
+            .header(AUTHORIZATION, &credentials.authorization());
+        let body = send_with_retry(&self.config, request).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        params: Vec<(&str, String)>,
+    ) -> Result<T, MixpanelError> {
+        let url = self.config.query_url(endpoint);
+        let request = self.get(&url, params)?.header(ACCEPT, "application/json");
+        let body = send_with_retry(&self.config, request).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// An authenticated `GET` for `url` with `params` and the project id.
+    fn get(
+        &self,
+        url: &str,
+        mut params: Vec<(&str, String)>,
+    ) -> Result<HttpRequest, MixpanelError> {
+        let credentials = self.credentials()?;
+        params.extend(project_param(credentials));
+        Ok(HttpRequest::get(&with_params(url, params))
+            .header(AUTHORIZATION, &credentials.authorization()))
+    }
+
+    fn credentials(&self) -> Result<&Credentials, MixpanelError> {
+        require_credentials(&self.config, "the query API")
+    }
+}
+
+fn project_param(credentials: &Credentials) -> Vec<(&'static str, String)> {
+    credentials
+        .project_id()
+        .map(|project_id| ("project_id", project_id.to_string()))
+        .into_iter()
+        .collect()
+}
+
+fn format_date(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn encode(params: Vec<(&str, String)>) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish()
+}
+
+fn with_params(url: &str, params: Vec<(&str, String)>) -> String {
+    if params.is_empty() {
+        return url.to_string();
+    }
+    format!("{url}?{}", encode(params))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::RecordingTransport;
+    use serde_json::json;
+
+    fn query(transport: &RecordingTransport) -> MixpanelQuery {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .build()
+            .unwrap();
+        MixpanelQuery::new(Arc::new(config))
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_ndjson() {
+        let transport = RecordingTransport::new();
+        transport.push_response(
+            200,
+            "{\"event\":\"a\",\"properties\":{\"distinct_id\":\"u1\",\"time\":1}}\n\n\
+             {\"event\":\"b\",\"properties\":{\"distinct_id\":\"u2\",\"time\":2}}",
+        );
+        let export = ExportQuery::new(date("2024-01-01"), date("2024-01-02")).event("a");
+
+        let events = query(&transport)
+            .export(&export)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].event, "b");
+        assert_eq!(events[1].distinct_id(), Some("u2"));
+        let request = &transport.requests()[0];
+        assert_eq!(
+            request.url,
+            "https://data.mixpanel.com/api/2.0/export?from_date=2024-01-01&to_date=2024-01-02\
+             &event=%5B%22a%22%5D&project_id=42"
+        );
+        assert!(request.headers.contains_key(AUTHORIZATION));
+    }
+
+    #[tokio::test]
+    async fn test_segmentation_is_typed() {
+        let transport = RecordingTransport::new();
+        transport.push_json(
+            200,
+            json!({
+                "data": {
+                    "series": ["2024-01-01", "2024-01-02"],
+                    "values": { "signup": { "2024-01-01": 3, "2024-01-02": 4 } }
+                },
+                "legend_size": 1
+            }),
+        );
+        let segmentation = query(&transport)
+            .segmentation(&SegmentationQuery::new(
+                "signup",
+                date("2024-01-01"),
+                date("2024-01-02"),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(segmentation.series.len(), 2);
+        assert_eq!(segmentation.total("signup"), 7.0);
+        assert!(transport.requests()[0]
+            .url
+            .starts_with("https://mixpanel.com/api/query/segmentation?event=signup"));
+    }
+
+    #[tokio::test]
+    async fn test_engage_all_follows_pages() {
+        let transport = RecordingTransport::new();
+        let profile = |id: &str| json!({ "$distinct_id": id, "$properties": {} });
+        transport
+            .push_json(
+                200,
+                json!({ "page": 0, "page_size": 1, "session_id": "s", "total": 2,
+                        "results": [profile("a")] }),
+            )
+            .push_json(
+                200,
+                json!({ "page": 1, "page_size": 1, "session_id": "s", "total": 2,
+                        "results": [profile("b")] }),
+            );
+
+        let profiles = query(&transport)
+            .engage_all(&EngageQuery::new().filter("properties[\"plan\"] == \"pro\""))
+            .await
+            .unwrap();
+
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[1].distinct_id, "b");
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        let form = String::from_utf8(requests[1].body.clone()).unwrap();
+        assert!(form.ends_with("&page=1&session_id=s"));
+    }
+
+    #[tokio::test]
+    async fn test_queries_require_credentials() {
+        let config = Config::builder()
+            .transport(Arc::new(RecordingTransport::new()))
+            .build()
+            .unwrap();
+        let err = MixpanelQuery::new(Arc::new(config))
+            .insights(1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MixpanelError::InvalidConfig(_)));
+    }
+}
@@ -0,0 +1,482 @@
+use crate::errors::MixpanelError;
+use flate2::read::GzDecoder;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Client, Method, StatusCode};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::future::Future;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An HTTP request built by the client, independent of the HTTP library that
+/// ends up sending it.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn post(url: &str, body: Vec<u8>) -> Self {
+        Self {
+            method: Method::POST,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body,
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        Self {
+            method: Method::GET,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: reqwest::header::HeaderName, value: &str) -> Self {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            self.headers.insert(name, value);
+        }
+        self
+    }
+
+    /// The body parsed as JSON, if it is JSON. Gzipped bodies are
+    /// decompressed first.
+    pub fn json(&self) -> Option<Value> {
+        let gzipped = self
+            .headers
+            .get(CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding == "gzip");
+        if gzipped {
+            let mut body = Vec::new();
+            GzDecoder::new(self.body.as_slice())
+                .read_to_end(&mut body)
+                .ok()?;
+            serde_json::from_slice(&body).ok()
+        } else {
+            serde_json::from_slice(&self.body).ok()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+pub type TransportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<HttpResponse, MixpanelError>> + Send + 'a>>;
+
+pub type StreamingFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<StreamingResponse, MixpanelError>> + Send + 'a>>;
+
+pub type ChunkFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>, MixpanelError>> + Send + 'a>>;
+
+/// A response body that is read chunk by chunk. `None` marks the end of the
+/// body.
+pub trait BodyStream: Send {
+    fn next_chunk(&mut self) -> ChunkFuture<'_>;
+}
+
+/// A response whose body has not been read yet, for large downloads such as
+/// the raw event export.
+pub struct StreamingResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Box<dyn BodyStream>,
+}
+
+impl StreamingResponse {
+    /// Reads the rest of the body into memory.
+    pub async fn into_response(mut self) -> Result<HttpResponse, MixpanelError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.body.next_chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(HttpResponse {
+            status: self.status,
+            headers: self.headers,
+            body,
+        })
+    }
+}
+
+impl From<HttpResponse> for StreamingResponse {
+    fn from(response: HttpResponse) -> Self {
+        Self {
+            status: response.status,
+            headers: response.headers,
+            body: Box::new(BufferedBody(Some(response.body))),
+        }
+    }
+}
+
+/// A body that is already in memory, yielded as a single chunk.
+struct BufferedBody(Option<Vec<u8>>);
+
+impl BodyStream for BufferedBody {
+    fn next_chunk(&mut self) -> ChunkFuture<'_> {
+        let chunk = self.0.take().filter(|body| !body.is_empty());
+        Box::pin(async move { Ok(chunk) })
+    }
+}
+
+/// Sends the HTTP requests made by the client.
+///
+/// Non-2xx responses are returned as `Ok`; the client decides what to do
+/// with them. Errors are reserved for requests that got no response at all.
+pub trait Transport: Debug + Send + Sync {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_>;
+
+    /// Sends `request` without waiting for the whole body. The default
+    /// implementation buffers the body with `send`.
+    fn send_streaming(&self, request: HttpRequest) -> StreamingFuture<'_> {
+        Box::pin(async move { self.send(request).await.map(StreamingResponse::from) })
+    }
+}
+
+/// The default transport, backed by a pooled `reqwest::Client`.
+///
+/// Streamed responses can go through a second client, so that a total
+/// request timeout does not cut off long downloads. Those are bounded by
+/// `read_timeout`, the longest wait for the headers or for the next chunk.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+    streaming: Option<Client>,
+    read_timeout: Option<Duration>,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            streaming: None,
+            read_timeout: None,
+        }
+    }
+
+    /// Sends streamed requests through `client` instead, waiting at most
+    /// `read_timeout` for each read.
+    pub fn with_streaming(mut self, client: Client, read_timeout: Option<Duration>) -> Self {
+        self.streaming = Some(client);
+        self.read_timeout = read_timeout;
+        self
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+        Box::pin(async move {
+            let res = self
+                .client
+                .request(request.method, &request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send()
+                .await?;
+            let status = res.status();
+            let headers = res.headers().clone();
+            let body = res.bytes().await?.to_vec();
+            Ok(HttpResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+
+    fn send_streaming(&self, request: HttpRequest) -> StreamingFuture<'_> {
+        Box::pin(async move {
+            let client = self.streaming.as_ref().unwrap_or(&self.client);
+            let send = client
+                .request(request.method, &request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .send();
+            let res = with_read_timeout(self.read_timeout, send).await??;
+            Ok(StreamingResponse {
+                status: res.status(),
+                headers: res.headers().clone(),
+                body: Box::new(TimedBody {
+                    response: res,
+                    read_timeout: self.read_timeout,
+                }),
+            })
+        })
+    }
+}
+
+impl BodyStream for reqwest::Response {
+    fn next_chunk(&mut self) -> ChunkFuture<'_> {
+        Box::pin(async move { Ok(self.chunk().await?.map(|chunk| chunk.to_vec())) })
+    }
+}
+
+/// A streamed body whose every read is bounded by `read_timeout`.
+struct TimedBody {
+    response: reqwest::Response,
+    read_timeout: Option<Duration>,
+}
+
+impl BodyStream for TimedBody {
+    fn next_chunk(&mut self) -> ChunkFuture<'_> {
+        Box::pin(async move {
+            let chunk = with_read_timeout(self.read_timeout, self.response.chunk()).await??;
+            Ok(chunk.map(|chunk| chunk.to_vec()))
+        })
+    }
+}
+
+async fn with_read_timeout<T>(
+    read_timeout: Option<Duration>,
+    read: impl Future<Output = T>,
+) -> Result<T, MixpanelError> {
+    match read_timeout {
+        Some(limit) => {
+            tokio::time::timeout(limit, read)
+                .await
+                .map_err(|_| MixpanelError::Transport {
+                    message: format!("no data received for {limit:?}"),
+                    retryable: true,
+                })
+        }
+        None => Ok(read.await),
+    }
+}
+
+/// A transport backed by a blocking `reqwest` client. Its futures are ready
+/// as soon as they are created, which is what lets `blocking::Mixpanel`
+/// drive the async client without a runtime.
+#[cfg(feature = "blocking")]
+#[derive(Debug, Clone, Default)]
+pub struct BlockingTransport {
+    client: reqwest::blocking::Client,
+}
+
+#[cfg(feature = "blocking")]
+impl BlockingTransport {
+    pub fn new(client: reqwest::blocking::Client) -> Self {
+        Self { client }
+    }
+
+    fn execute(&self, request: HttpRequest) -> Result<HttpResponse, MixpanelError> {
+        let res = self
+            .client
+            .request(request.method, &request.url)
+            .headers(request.headers)
+            .body(request.body)
+            .send()?;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = res.bytes()?.to_vec();
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Transport for BlockingTransport {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+        Box::pin(std::future::ready(self.execute(request)))
+    }
+}
+
+#[derive(Debug)]
+enum Scripted {
+    Response(HttpResponse),
+    Error { message: String, retryable: bool },
+}
+
+#[derive(Debug, Default)]
+struct Recording {
+    requests: Vec<HttpRequest>,
+    responses: VecDeque<Scripted>,
+}
+
+/// A transport for tests: records every request and answers with scripted
+/// responses, in order. Once the script runs out it answers like a
+/// successful verbose ingestion call.
+///
+/// Clones share the same recording, so a clone can be handed to `Config`
+/// while the original is kept for assertions.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingTransport {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl RecordingTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_response(&self, status: u16, body: &str) -> &Self {
+        self.push_response_with_headers(status, HeaderMap::new(), body)
+    }
+
+    pub fn push_response_with_headers(&self, status: u16, headers: HeaderMap, body: &str) -> &Self {
+        self.recording
+            .lock()
+            .unwrap()
+            .responses
+            .push_back(Scripted::Response(HttpResponse {
+                status: StatusCode::from_u16(status).expect("valid status code"),
+                headers,
+                body: body.as_bytes().to_vec(),
+            }));
+        self
+    }
+
+    pub fn push_json(&self, status: u16, body: Value) -> &Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        self.push_response_with_headers(status, headers, &body.to_string())
+    }
+
+    /// Scripts a request that gets no response, such as a timeout when
+    /// `retryable` is set.
+    pub fn push_error(&self, message: &str, retryable: bool) -> &Self {
+        self.recording
+            .lock()
+            .unwrap()
+            .responses
+            .push_back(Scripted::Error {
+                message: message.to_string(),
+                retryable,
+            });
+        self
+    }
+
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.recording.lock().unwrap().requests.clone()
+    }
+
+    /// JSON bodies of all recorded requests, in order.
+    pub fn json_bodies(&self) -> Vec<Value> {
+        self.requests()
+            .iter()
+            .map(|request| request.json().unwrap_or(Value::Null))
+            .collect()
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, request: HttpRequest) -> TransportFuture<'_> {
+        let scripted = {
+            let mut recording = self.recording.lock().unwrap();
+            recording.requests.push(request);
+            recording.responses.pop_front()
+        };
+        Box::pin(async move {
+            match scripted {
+                Some(Scripted::Response(response)) => Ok(response),
+                Some(Scripted::Error { message, retryable }) => {
+                    Err(MixpanelError::Transport { message, retryable })
+                }
+                None => Ok(HttpResponse {
+                    status: StatusCode::OK,
+                    headers: HeaderMap::new(),
+                    body: json!({ "status": 1, "error": null })
+                        .to_string()
+                        .into_bytes(),
+                }),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Config;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves one chunked response, writing `chunks` with `pause` before each.
+    async fn slow_server(chunks: Vec<&'static str>, pause: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n")
+                .await
+                .unwrap();
+            for chunk in chunks {
+                tokio::time::sleep(pause).await;
+                let frame = format!("{:x}\r\n{chunk}\r\n", chunk.len());
+                if socket.write_all(frame.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            let _ = socket.write_all(b"0\r\n\r\n").await;
+        });
+        format!("http://{addr}/export")
+    }
+
+    #[tokio::test]
+    async fn test_streams_outlive_the_request_timeout() {
+        let url = slow_server(vec!["a", "b", "c", "d"], Duration::from_millis(100)).await;
+        let config = Config::builder()
+            .timeout(Some(Duration::from_millis(150)))
+            .read_timeout(Some(Duration::from_secs(5)))
+            .build()
+            .unwrap();
+
+        let response = config
+            .transport
+            .send_streaming(HttpRequest::get(&url))
+            .await
+            .unwrap()
+            .into_response()
+            .await
+            .unwrap();
+        assert_eq!(response.text(), "abcd");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_streams_hit_the_read_timeout() {
+        let url = slow_server(vec!["a", "b"], Duration::from_millis(300)).await;
+        let config = Config::builder()
+            .read_timeout(Some(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+
+        let result = match config
+            .transport
+            .send_streaming(HttpRequest::get(&url))
+            .await
+        {
+            Ok(response) => response.into_response().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        assert!(matches!(
+            result,
+            Err(MixpanelError::Transport {
+                retryable: true,
+                ..
+            })
+        ));
+    }
+}
@@ -1,16 +1,592 @@
+#[cfg(feature = "blocking")]
+use crate::blocking::BlockingConfig;
+use crate::errors::MixpanelError;
+#[cfg(feature = "blocking")]
+use crate::transport::BlockingTransport;
+use crate::transport::{ReqwestTransport, Transport};
+use crate::validation::ValidationConfig;
+use base64::prelude::*;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, ClientBuilder, Proxy, StatusCode};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::event::Event;
+
+/// Maximum number of records Mixpanel accepts in a single `/track`,
+/// `/engage` or `/groups` request.
+pub const MAX_BATCH_SIZE: usize = 2000;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub debug: bool,
-    pub host: String,
-    pub protocol: String,
+    /// Data residency region; decides which hosts requests are sent to.
+    pub region: Region,
+    /// Sends every request made with this config. The default transport owns
+    /// a pooled HTTP client, so cloning the config (or the `Arc` around it)
+    /// reuses the same connections.
+    pub transport: Arc<dyn Transport>,
+    pub retry: RetryPolicy,
+    /// Needed for `/import` and the other endpoints that do not accept the
+    /// project token alone.
+    pub credentials: Option<Credentials>,
+    pub compression: Compression,
+    pub id_merge: IdMerge,
+    /// Checks applied to every event before it is sent.
+    pub validation: ValidationConfig,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// URL of an ingestion (`/track`, `/engage`, `/groups`) or `/import`
+    /// endpoint.
+    pub fn api_url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.region.api_base(), endpoint)
+    }
+
+    /// URL of a Query or App API endpoint, relative to `/api`.
+    pub fn query_url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.region.query_base(), endpoint)
+    }
+
+    /// URL of a Raw Event Export endpoint, relative to `/api/2.0`.
+    pub fn export_url(&self, endpoint: &str) -> String {
+        format!("{}{}", self.region.export_base(), endpoint)
+    }
 }
 
 impl Default for Config {
+    fn default() -> Self {
+        ConfigBuilder::default()
+            .build()
+            .expect("default Mixpanel HTTP client should build")
+    }
+}
+
+/// Builder for `Config` and the HTTP client it owns.
+#[derive(Debug, Clone)]
+pub struct ConfigBuilder {
+    debug: bool,
+    region: Region,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Duration>,
+    proxy: Option<String>,
+    user_agent: String,
+    headers: Vec<(String, String)>,
+    retry: RetryPolicy,
+    transport: Option<Arc<dyn Transport>>,
+    credentials: Option<Credentials>,
+    compression: Compression,
+    id_merge: IdMerge,
+    validation: ValidationConfig,
+}
+
+impl Default for ConfigBuilder {
     fn default() -> Self {
         Self {
             debug: false,
-            host: "api.mixpanel.com".to_string(),
-            protocol: "https".to_string(),
+            region: Region::default(),
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(30)),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            proxy: None,
+            user_agent: concat!("mixpanel-rs/", env!("CARGO_PKG_VERSION")).to_string(),
+            headers: Vec::new(),
+            retry: RetryPolicy::default(),
+            transport: None,
+            credentials: None,
+            compression: Compression::None,
+            id_merge: IdMerge::default(),
+            validation: ValidationConfig::default(),
+        }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Timeout for establishing a connection. `None` disables it.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Timeout for a whole request, from connecting until the body is read.
+    /// `None` disables it. Streamed responses such as the raw export are not
+    /// subject to it; see `read_timeout`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Longest wait for the headers or the next chunk of a streamed
+    /// response. `None` disables it.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Maximum number of idle keep-alive connections kept per host.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// How long an idle pooled connection is kept before it is closed.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Routes all traffic through the given proxy URL.
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Adds a header sent with every request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn id_merge(mut self, id_merge: IdMerge) -> Self {
+        self.id_merge = id_merge;
+        self
+    }
+
+    pub fn validation(mut self, validation: ValidationConfig) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Sends requests through `transport` instead of the default reqwest
+    /// client. The HTTP client settings of this builder are then ignored.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    pub fn build(self) -> Result<Config, MixpanelError> {
+        if let Some(transport) = self.transport {
+            return Ok(Config {
+                debug: self.debug,
+                region: self.region,
+                transport,
+                retry: self.retry,
+                credentials: self.credentials,
+                compression: self.compression,
+                id_merge: self.id_merge,
+                validation: self.validation,
+            });
+        }
+
+        let mut client = self.client_builder()?;
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        let transport = ReqwestTransport::new(client.build()?)
+            .with_streaming(self.client_builder()?.build()?, self.read_timeout);
+
+        Ok(Config {
+            debug: self.debug,
+            region: self.region,
+            transport: Arc::new(transport),
+            retry: self.retry,
+            credentials: self.credentials,
+            compression: self.compression,
+            id_merge: self.id_merge,
+            validation: self.validation,
+        })
+    }
+
+    /// Every setting of the async client except the total timeout.
+    fn client_builder(&self) -> Result<ClientBuilder, MixpanelError> {
+        let mut builder = Client::builder()
+            .user_agent(self.user_agent.clone())
+            .default_headers(self.default_headers()?);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
         }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        Ok(builder)
     }
+
+    /// Like `build`, but the default transport uses a blocking HTTP client,
+    /// as `blocking::Mixpanel` needs. A custom transport must resolve its
+    /// futures without a runtime.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(mut self) -> Result<BlockingConfig, MixpanelError> {
+        if self.transport.is_none() {
+            let mut builder = reqwest::blocking::Client::builder()
+                .user_agent(self.user_agent.clone())
+                .default_headers(self.default_headers()?);
+            if let Some(timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(timeout);
+            }
+            // The blocking client has a 30s timeout by default; keep
+            // `timeout(None)` meaning no timeout, as for the async client.
+            builder = builder.timeout(self.timeout);
+            if let Some(max) = self.pool_max_idle_per_host {
+                builder = builder.pool_max_idle_per_host(max);
+            }
+            if let Some(timeout) = self.pool_idle_timeout {
+                builder = builder.pool_idle_timeout(timeout);
+            }
+            if let Some(proxy) = &self.proxy {
+                builder = builder.proxy(Proxy::all(proxy)?);
+            }
+            self.transport = Some(Arc::new(BlockingTransport::new(builder.build()?)));
+        }
+        self.build().map(BlockingConfig::new)
+    }
+
+    fn default_headers(&self) -> Result<HeaderMap, MixpanelError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| MixpanelError::InvalidConfig(format!("header {name}: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| MixpanelError::InvalidConfig(format!("header {name}: {e}")))?;
+            headers.insert(name, value);
+        }
+        Ok(headers)
+    }
+}
+
+/// Request body compression for the ingestion and `/import` endpoints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Gzip bodies of at least `min_size` bytes. Small bodies are sent as-is,
+    /// since compressing them costs more than it saves.
+    Gzip { min_size: usize },
+}
+
+/// The identity management API the project is set up with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdMerge {
+    /// Identities are linked by `$identify`, `$create_alias` and `$merge`
+    /// events.
+    #[default]
+    Original,
+    /// Identities are linked by any event that carries both `$device_id` and
+    /// `$user_id`. Events without a `distinct_id` get one derived from those
+    /// two properties.
+    Simplified,
+}
+
+impl FromStr for IdMerge {
+    type Err = MixpanelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "original" => Ok(IdMerge::Original),
+            "simplified" => Ok(IdMerge::Simplified),
+            other => Err(MixpanelError::InvalidConfig(format!(
+                "unknown ID merge API {other:?}"
+            ))),
+        }
+    }
+}
+
+/// Credentials for the endpoints that need more than the project token.
+///
+/// Service accounts are Mixpanel's recommended option and work for every
+/// authenticated API; the project API secret is only accepted by `/import`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    ServiceAccount {
+        username: String,
+        secret: String,
+        project_id: String,
+    },
+    ApiSecret(String),
+}
+
+impl Credentials {
+    pub fn service_account(username: &str, secret: &str, project_id: &str) -> Self {
+        Credentials::ServiceAccount {
+            username: username.to_string(),
+            secret: secret.to_string(),
+            project_id: project_id.to_string(),
+        }
+    }
+
+    pub fn api_secret(secret: &str) -> Self {
+        Credentials::ApiSecret(secret.to_string())
+    }
+
+    /// Project the service account acts on. Requests authenticated with an
+    /// API secret are already scoped to its project.
+    pub fn project_id(&self) -> Option<&str> {
+        match self {
+            Credentials::ServiceAccount { project_id, .. } => Some(project_id),
+            Credentials::ApiSecret(_) => None,
+        }
+    }
+
+    /// Value of the `Authorization` header.
+    pub fn authorization(&self) -> String {
+        let user_pass = match self {
+            Credentials::ServiceAccount {
+                username, secret, ..
+            } => format!("{username}:{secret}"),
+            Credentials::ApiSecret(secret) => format!("{secret}:"),
+        };
+        format!("Basic {}", BASE64_STANDARD.encode(user_pass))
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::ServiceAccount {
+                username,
+                project_id,
+                ..
+            } => f
+                .debug_struct("ServiceAccount")
+                .field("username", username)
+                .field("secret", &"<redacted>")
+                .field("project_id", project_id)
+                .finish(),
+            Credentials::ApiSecret(_) => f.debug_tuple("ApiSecret").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+/// Mixpanel data residency region.
+///
+/// Projects can only ingest and query data through the hosts of the region
+/// they were created in. `Custom` sends every request to the given base URL,
+/// which is mainly useful to point the client at a local mock server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Region {
+    #[default]
+    Us,
+    Eu,
+    India,
+    /// Base URL such as `http://127.0.0.1:8080`, without a trailing slash.
+    Custom(String),
+}
+
+impl Region {
+    /// Base URL for ingestion and `/import`.
+    pub fn api_base(&self) -> &str {
+        match self {
+            Region::Us => "https://api.mixpanel.com",
+            Region::Eu => "https://api-eu.mixpanel.com",
+            Region::India => "https://api-in.mixpanel.com",
+            Region::Custom(url) => url.trim_end_matches('/'),
+        }
+    }
+
+    /// Base URL for the Query and App APIs.
+    pub fn query_base(&self) -> &str {
+        match self {
+            Region::Us => "https://mixpanel.com/api",
+            Region::Eu => "https://eu.mixpanel.com/api",
+            Region::India => "https://in.mixpanel.com/api",
+            Region::Custom(url) => url.trim_end_matches('/'),
+        }
+    }
+
+    /// Base URL for Raw Event Export.
+    pub fn export_base(&self) -> &str {
+        match self {
+            Region::Us => "https://data.mixpanel.com/api/2.0",
+            Region::Eu => "https://data-eu.mixpanel.com/api/2.0",
+            Region::India => "https://data-in.mixpanel.com/api/2.0",
+            Region::Custom(url) => url.trim_end_matches('/'),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = MixpanelError;
+
+    /// Accepts `us`, `eu`, `in`/`india` (case-insensitive) or an `http(s)://`
+    /// base URL.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "us" => Ok(Region::Us),
+            "eu" => Ok(Region::Eu),
+            "in" | "india" => Ok(Region::India),
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(Region::Custom(s.trim().trim_end_matches('/').to_string()))
+            }
+            other => Err(MixpanelError::InvalidConfig(format!(
+                "unknown Mixpanel region {other:?}"
+            ))),
+        }
+    }
+}
+
+/// Controls how failed requests are retried.
+///
+/// Delays grow exponentially from `base_delay` and are capped at `max_delay`.
+/// A `Retry-After` header on the response takes precedence over the computed
+/// delay. When it asks for a longer wait than `max_delay` the request is not
+/// retried at all, rather than retried before the server allows. Client
+/// errors other than 408 and 429 are never retried, whatever
+/// `retry_statuses` says, since resending a payload Mixpanel rejected as
+/// invalid cannot succeed.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Randomises each delay to between half and all of its computed value so
+    /// that concurrent clients do not retry in lockstep.
+    pub jitter: bool,
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_statuses: vec![429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn should_retry(&self, status: StatusCode) -> bool {
+        let retryable_client_error =
+            status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT;
+        if status.is_client_error() && !retryable_client_error {
+            return false;
+        }
+        self.retry_statuses.contains(&status.as_u16())
+    }
+
+    /// Delay before the attempt following `attempt` (1-based), or `None` when
+    /// the server's `Retry-After` exceeds `max_delay` and the caller should
+    /// give up.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let delay = if self.jitter {
+            let half = exp / 2;
+            half + half.mul_f64(rand::random::<f64>())
+        } else {
+            exp
+        };
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// Body of a verbose `/track`, `/engage` or `/groups` response. `status` is
+/// `1` when every record was accepted and `0` otherwise, with `error`
+/// explaining why.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TrackResponse {
+    pub status: i64,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Body of a successful strict `/import` response.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ImportResponse {
+    pub code: u16,
+    pub num_records_imported: u64,
+    pub status: String,
+}
+
+/// A record rejected by a strict `/import` request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FailedRecord {
+    /// Position of the record in the request.
+    pub index: usize,
+    #[serde(rename = "$insert_id", default)]
+    pub insert_id: Option<String>,
+    #[serde(default)]
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ImportFailure {
+    #[serde(default)]
+    pub num_records_imported: u64,
+    #[serde(default)]
+    pub failed_records: Vec<FailedRecord>,
+}
+
+/// Outcome of one chunk of a batched request.
+///
+/// `offset` and `len` locate the chunk inside the input that was passed to
+/// the batch call, so callers can tell which records failed.
+#[derive(Debug)]
+pub struct BatchResult<T = TrackResponse> {
+    pub offset: usize,
+    pub len: usize,
+    pub result: Result<T, MixpanelError>,
 }
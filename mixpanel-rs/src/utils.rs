@@ -1,44 +1,372 @@
 use crate::errors::MixpanelError;
-use crate::types::Config;
-use reqwest::Client;
+use crate::transport::{HttpRequest, StreamingResponse};
+use crate::types::{
+    BatchResult, Compression, Config, Credentials, ImportFailure, ImportResponse, TrackResponse,
+    MAX_BATCH_SIZE,
+};
+use flate2::write::GzEncoder;
+use reqwest::header::{
+    HeaderMap, ACCEPT, AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, RETRY_AFTER,
+};
+use reqwest::StatusCode;
 use serde_json::{json, Value};
+use std::io::Write;
+use std::time::Duration;
+use url::form_urlencoded;
 
 #[cfg(feature = "tracing")]
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
+/// Sends a single record to an ingestion endpoint (`/track`, `/engage`,
+/// `/groups`) and returns Mixpanel's verbose response.
 #[cfg_attr(feature = "tracing", instrument(skip(config, payload)))]
 pub async fn send_request(
     config: &Config,
     endpoint: &str,
     payload: Value,
-) -> Result<Value, MixpanelError> {
-    let client = Client::new();
-    let url = format!("{}://{}{}", config.protocol, config.host, endpoint);
-    let payload = json!([payload]);
+) -> Result<TrackResponse, MixpanelError> {
+    send_records(config, endpoint, vec![payload]).await
+}
+
+/// Sends `records` in as few requests as possible, splitting them into
+/// chunks of at most `MAX_BATCH_SIZE`. Every chunk is reported separately so a
+/// failing chunk does not hide the ones that were accepted.
+#[cfg_attr(feature = "tracing", instrument(skip(config, records), fields(count = records.len())))]
+pub async fn send_batch_request(
+    config: &Config,
+    endpoint: &str,
+    records: Vec<Value>,
+) -> Vec<BatchResult> {
+    let mut results = Vec::with_capacity(records.len().div_ceil(MAX_BATCH_SIZE));
+    let mut offset = 0;
+    let mut records = records.into_iter().peekable();
+    while records.peek().is_some() {
+        let chunk: Vec<Value> = records.by_ref().take(MAX_BATCH_SIZE).collect();
+        let len = chunk.len();
+        let result = send_records(config, endpoint, chunk).await;
+        results.push(BatchResult {
+            offset,
+            len,
+            result,
+        });
+        offset += len;
+    }
+    results
+}
+
+/// Sends records to `/import` in strict mode, authenticated with the
+/// config's credentials. Records Mixpanel rejects are reported through
+/// `MixpanelError::PartialFailure`.
+#[cfg_attr(feature = "tracing", instrument(skip(config, records), fields(count = records.len())))]
+pub async fn send_import_request(
+    config: &Config,
+    records: Vec<Value>,
+) -> Result<ImportResponse, MixpanelError> {
+    let credentials = require_credentials(config, "/import")?;
+    let query = {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query.append_pair("strict", "1");
+        if let Some(project_id) = credentials.project_id() {
+            query.append_pair("project_id", project_id);
+        }
+        query.finish()
+    };
+    let url = config.api_url(&format!("/import?{query}"));
+    let request =
+        json_request(&url, &json!(records))?.header(AUTHORIZATION, &credentials.authorization());
+    let request = compress(config, request)?;
+    match send_with_retry(config, request).await {
+        Ok(body) => Ok(serde_json::from_str(&body)?),
+        Err(MixpanelError::ApiError { status, body }) => {
+            match serde_json::from_str::<ImportFailure>(&body) {
+                Ok(failure) if !failure.failed_records.is_empty() => {
+                    Err(MixpanelError::PartialFailure {
+                        imported: failure.num_records_imported,
+                        failed: failure.failed_records,
+                    })
+                }
+                _ => Err(MixpanelError::ApiError { status, body }),
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Like `send_batch_request`, for `/import`.
+pub async fn send_import_batch_request(
+    config: &Config,
+    records: Vec<Value>,
+) -> Vec<BatchResult<ImportResponse>> {
+    let mut results = Vec::with_capacity(records.len().div_ceil(MAX_BATCH_SIZE));
+    let mut offset = 0;
+    let mut records = records.into_iter().peekable();
+    while records.peek().is_some() {
+        let chunk: Vec<Value> = records.by_ref().take(MAX_BATCH_SIZE).collect();
+        let len = chunk.len();
+        let result = send_import_request(config, chunk).await;
+        results.push(BatchResult {
+            offset,
+            len,
+            result,
+        });
+        offset += len;
+    }
+    results
+}
+
+async fn send_records(
+    config: &Config,
+    endpoint: &str,
+    records: Vec<Value>,
+) -> Result<TrackResponse, MixpanelError> {
+    let url = config.api_url(&format!("{endpoint}?verbose=1"));
+    let request = compress(config, json_request(&url, &json!(records))?)?;
+    let body = send_with_retry(config, request).await?;
+    let response: TrackResponse = serde_json::from_str(&body)?;
+    if response.status == 1 {
+        Ok(response)
+    } else {
+        Err(MixpanelError::Rejected(response.error.unwrap_or(body)))
+    }
+}
+
+/// The config's credentials, for APIs that cannot be used with the project
+/// token alone.
+pub(crate) fn require_credentials<'a>(
+    config: &'a Config,
+    api: &str,
+) -> Result<&'a Credentials, MixpanelError> {
+    config.credentials.as_ref().ok_or_else(|| {
+        MixpanelError::InvalidConfig(format!(
+            "{api} requires service account or API secret credentials"
+        ))
+    })
+}
+
+/// A JSON `POST` request for `payload`.
+pub(crate) fn json_request(url: &str, payload: &Value) -> Result<HttpRequest, MixpanelError> {
+    Ok(HttpRequest::post(url, serde_json::to_vec(payload)?)
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json"))
+}
+
+/// Applies `config.compression` to the request body.
+fn compress(config: &Config, mut request: HttpRequest) -> Result<HttpRequest, MixpanelError> {
+    match config.compression {
+        Compression::Gzip { min_size } if request.body.len() >= min_size => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(&request.body)
+                .and_then(|_| encoder.finish())
+                .map(|body| request.body = body)
+                .map_err(|e| MixpanelError::Other(format!("gzip failed: {e}")))?;
+            Ok(request.header(CONTENT_ENCODING, "gzip"))
+        }
+        _ => Ok(request),
+    }
+}
+
+/// Sends `request`, retrying according to `config.retry`, and returns the
+/// response body of the first successful attempt.
+pub(crate) async fn send_with_retry(
+    config: &Config,
+    request: HttpRequest,
+) -> Result<String, MixpanelError> {
     #[cfg(feature = "tracing")]
-    debug!(%url, body = ?payload, "Sending request to Mixpanel");
-
-    let res = client
-        .post(&url)
-        .header("Content-Type", "application/json")
-        .header("Accept", "text/plain")
-        .json(&payload)
-        .send()
-        .await?;
-
-    let status = res.status();
-    let body = res
-        .text()
-        .await
-        .unwrap_or_else(|_| "<could not read body>".into());
+    debug!(url = %request.url, body = %String::from_utf8_lossy(&request.body), "Sending request to Mixpanel");
+
+    let mut attempt = 1;
+    loop {
+        let (error, retry_after) = match execute(config, request.clone()).await {
+            Ok(body) => return Ok(body),
+            Err(PostError::Status {
+                status,
+                body,
+                retry_after,
+            }) if config.retry.should_retry(status) => {
+                (MixpanelError::ApiError { status, body }, retry_after)
+            }
+            Err(PostError::Status { status, body, .. }) => {
+                return Err(MixpanelError::ApiError { status, body })
+            }
+            Err(PostError::Transport(e)) if is_retryable(&e) => (e, None),
+            Err(PostError::Transport(e)) => return Err(e),
+        };
+
+        if attempt >= config.retry.max_attempts {
+            return Err(error);
+        }
+        let Some(delay) = config.retry.delay(attempt, retry_after) else {
+            return Err(error);
+        };
+        #[cfg(feature = "tracing")]
+        warn!(attempt, ?delay, error = %error, "Retrying Mixpanel request");
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Like `send_with_retry`, but hands back a successful response with its
+/// body still unread.
+pub(crate) async fn send_streaming_with_retry(
+    config: &Config,
+    request: HttpRequest,
+) -> Result<StreamingResponse, MixpanelError> {
+    #[cfg(feature = "tracing")]
+    debug!(url = %request.url, "Opening Mixpanel stream");
+
+    let mut attempt = 1;
+    loop {
+        let (error, retry_after) = match config.transport.send_streaming(request.clone()).await {
+            Ok(res) if res.status.is_success() => return Ok(res),
+            Ok(res) => {
+                let retry_after = retry_after(&res.headers);
+                let res = res.into_response().await?;
+                let status = res.status;
+                let error = MixpanelError::ApiError {
+                    status,
+                    body: res.text(),
+                };
+                if !config.retry.should_retry(status) {
+                    return Err(error);
+                }
+                (error, retry_after)
+            }
+            Err(e) if is_retryable(&e) => (e, None),
+            Err(e) => return Err(e),
+        };
+
+        if attempt >= config.retry.max_attempts {
+            return Err(error);
+        }
+        let Some(delay) = config.retry.delay(attempt, retry_after) else {
+            return Err(error);
+        };
+        #[cfg(feature = "tracing")]
+        warn!(attempt, ?delay, error = %error, "Retrying Mixpanel request");
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+enum PostError {
+    Transport(MixpanelError),
+    Status {
+        status: StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    },
+}
+
+async fn execute(config: &Config, request: HttpRequest) -> Result<String, PostError> {
+    let res = config.transport.send(request).await?;
+
+    let status = res.status;
+    let retry_after = retry_after(&res.headers);
+    let body = res.text();
 
     if status.is_success() {
         #[cfg(feature = "tracing")]
-        info!(status = ?status, payload = %payload,  body = %body, "Mixpanel request successful");
-        Ok(payload)
+        info!(status = ?status, body = %body, "Mixpanel request successful");
+        Ok(body)
     } else {
         #[cfg(feature = "tracing")]
         error!(status = ?status, body = %body, "Mixpanel API returned error");
-        Err(MixpanelError::ApiError { status, body })
+        Err(PostError::Status {
+            status,
+            body,
+            retry_after,
+        })
+    }
+}
+
+impl From<MixpanelError> for PostError {
+    fn from(e: MixpanelError) -> Self {
+        PostError::Transport(e)
+    }
+}
+
+/// Whether a request that got no response is worth sending again.
+fn is_retryable(e: &MixpanelError) -> bool {
+    match e {
+        MixpanelError::HttpError(e) => e.is_timeout() || e.is_connect(),
+        MixpanelError::Transport { retryable, .. } => *retryable,
+        _ => false,
+    }
+}
+
+/// Waits before a retry. The blocking client runs its futures without a
+/// Tokio runtime, so there the thread sleeps instead.
+async fn sleep(delay: Duration) {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        tokio::time::sleep(delay).await;
+    } else {
+        std::thread::sleep(delay);
+    }
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+/// Parses a `Retry-After` value, given either in seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RetryPolicy;
+
+    #[test]
+    fn test_parse_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("not a date"), None);
+    }
+
+    #[test]
+    fn test_retry_policy_skips_validation_errors() {
+        let policy = RetryPolicy {
+            retry_statuses: vec![400, 429, 503],
+            ..RetryPolicy::default()
+        };
+        assert!(!policy.should_retry(StatusCode::BAD_REQUEST));
+        assert!(policy.should_retry(StatusCode::TOO_MANY_REQUESTS));
+        assert!(policy.should_retry(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.should_retry(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(1, None), Some(Duration::from_millis(500)));
+        assert_eq!(policy.delay(2, None), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(10, None), Some(policy.max_delay));
+    }
+
+    #[test]
+    fn test_retry_after_is_honored_or_ends_retries() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(5))),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(60))), None);
     }
 }
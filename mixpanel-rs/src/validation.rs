@@ -0,0 +1,304 @@
+use crate::errors::MixpanelError;
+use crate::types::Event;
+use serde_json::{Map, Value};
+use std::fmt;
+
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+/// Largest integer a JavaScript number (and so Mixpanel) represents exactly.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Properties that are never dropped to get under `max_properties`.
+const RESERVED_PROPERTIES: &[&str] = &["token", "time", "distinct_id", "$insert_id"];
+
+/// What to do with an event that exceeds Mixpanel's limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Fail with `MixpanelError::Validation` without sending the event.
+    Reject,
+    /// Fix the event so Mixpanel keeps all of it, logging what was changed:
+    /// long strings are cut, surplus properties are dropped and unsafe
+    /// integers are sent as strings.
+    Truncate,
+    /// Send events unchanged and let Mixpanel apply its own limits.
+    #[default]
+    PassThrough,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub max_properties: usize,
+    pub max_event_name_bytes: usize,
+    pub max_string_bytes: usize,
+    /// Limit for the properties in `long_string_properties`.
+    pub max_long_string_bytes: usize,
+    pub long_string_properties: Vec<String>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_properties: 255,
+            max_event_name_bytes: 255,
+            max_string_bytes: 255,
+            max_long_string_bytes: 8 * 1024,
+            long_string_properties: ["$current_url", "$referrer", "$initial_referrer"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationConfig {
+    pub mode: ValidationMode,
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    TooManyProperties {
+        count: usize,
+        max: usize,
+    },
+    EventNameTooLong {
+        len: usize,
+        max: usize,
+    },
+    StringTooLong {
+        len: usize,
+        max: usize,
+    },
+    /// An integer outside the range JavaScript numbers represent exactly.
+    UnsafeInteger,
+}
+
+/// A limit an event exceeded. `property` is `None` for event-level
+/// violations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub property: Option<String>,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(property) = &self.property {
+            write!(f, "{property}: ")?;
+        }
+        match &self.kind {
+            ViolationKind::TooManyProperties { count, max } => {
+                write!(f, "{count} properties, at most {max} allowed")
+            }
+            ViolationKind::EventNameTooLong { len, max } => {
+                write!(f, "event name is {len} bytes, at most {max} allowed")
+            }
+            ViolationKind::StringTooLong { len, max } => {
+                write!(f, "string is {len} bytes, at most {max} allowed")
+            }
+            ViolationKind::UnsafeInteger => write!(f, "integer cannot be represented exactly"),
+        }
+    }
+}
+
+/// Checks `event` against `config.limits`.
+///
+/// Returns the violations that were found; in `Truncate` mode the event has
+/// already been fixed by then. In `Reject` mode any violation is an error.
+/// `PassThrough` skips the checks altogether.
+pub fn validate_event(
+    event: &mut Event,
+    config: &ValidationConfig,
+) -> Result<Vec<Violation>, MixpanelError> {
+    if config.mode == ValidationMode::PassThrough {
+        return Ok(Vec::new());
+    }
+    let truncate = config.mode == ValidationMode::Truncate;
+    let limits = &config.limits;
+    let mut violations = Vec::new();
+
+    if event.name.len() > limits.max_event_name_bytes {
+        violations.push(Violation {
+            property: None,
+            kind: ViolationKind::EventNameTooLong {
+                len: event.name.len(),
+                max: limits.max_event_name_bytes,
+            },
+        });
+        if truncate {
+            truncate_str(&mut event.name, limits.max_event_name_bytes);
+        }
+    }
+
+    if let Value::Object(props) = &mut event.properties {
+        if props.len() > limits.max_properties {
+            violations.push(Violation {
+                property: None,
+                kind: ViolationKind::TooManyProperties {
+                    count: props.len(),
+                    max: limits.max_properties,
+                },
+            });
+            if truncate {
+                drop_surplus(props, limits.max_properties);
+            }
+        }
+
+        for (key, value) in props.iter_mut() {
+            let max = if limits.long_string_properties.contains(key) {
+                limits.max_long_string_bytes
+            } else {
+                limits.max_string_bytes
+            };
+            check_value(key, value, max, truncate, &mut violations);
+            if let Value::Array(items) = value {
+                for item in items {
+                    check_value(key, item, max, truncate, &mut violations);
+                }
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(violations);
+    }
+    if !truncate {
+        return Err(MixpanelError::Validation(violations));
+    }
+    #[cfg(feature = "tracing")]
+    for violation in &violations {
+        warn!(event = %event.name, %violation, "Adjusted event to fit Mixpanel limits");
+    }
+    Ok(violations)
+}
+
+fn check_value(
+    key: &str,
+    value: &mut Value,
+    max_bytes: usize,
+    truncate: bool,
+    violations: &mut Vec<Violation>,
+) {
+    match value {
+        Value::String(s) if s.len() > max_bytes => {
+            violations.push(Violation {
+                property: Some(key.to_string()),
+                kind: ViolationKind::StringTooLong {
+                    len: s.len(),
+                    max: max_bytes,
+                },
+            });
+            if truncate {
+                truncate_str(s, max_bytes);
+            }
+        }
+        Value::Number(n) if !is_safe_number(n) => {
+            violations.push(Violation {
+                property: Some(key.to_string()),
+                kind: ViolationKind::UnsafeInteger,
+            });
+            if truncate {
+                *value = Value::String(n.to_string());
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_safe_number(n: &serde_json::Number) -> bool {
+    if let Some(i) = n.as_i64() {
+        i.unsigned_abs() <= MAX_SAFE_INTEGER
+    } else if let Some(u) = n.as_u64() {
+        u <= MAX_SAFE_INTEGER
+    } else {
+        true
+    }
+}
+
+/// Drops non-reserved properties, last key first, until at most `max` remain.
+fn drop_surplus(props: &mut Map<String, Value>, max: usize) {
+    let surplus: Vec<String> = props
+        .keys()
+        .rev()
+        .filter(|key| !RESERVED_PROPERTIES.contains(&key.as_str()))
+        .take(props.len().saturating_sub(max))
+        .cloned()
+        .collect();
+    for key in surplus {
+        props.remove(&key);
+    }
+}
+
+/// Cuts `s` to at most `max` bytes without splitting a character.
+fn truncate_str(s: &mut String, max: usize) {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s.truncate(end);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(mode: ValidationMode) -> ValidationConfig {
+        ValidationConfig {
+            mode,
+            ..ValidationConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_reject_reports_every_violation() {
+        let mut event = Event::new("viewed")
+            .property("title", "é".repeat(200))
+            .property("views", json!(u64::MAX));
+        let err = validate_event(&mut event, &config(ValidationMode::Reject)).unwrap_err();
+        let MixpanelError::Validation(violations) = err else {
+            panic!("expected validation error");
+        };
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[0].kind,
+            ViolationKind::StringTooLong { len: 400, max: 255 }
+        );
+        assert_eq!(violations[1].kind, ViolationKind::UnsafeInteger);
+    }
+
+    #[test]
+    fn test_truncate_fixes_event() {
+        let mut event = Event::new(&"e".repeat(300))
+            .distinct_id("user")
+            .property("title", "é".repeat(200))
+            .property("tags", json!(["x".repeat(300)]))
+            .property("$current_url", "u".repeat(1000))
+            .property("views", json!(u64::MAX));
+        for i in 0..300 {
+            event = event.property(&format!("z{i:03}"), i);
+        }
+
+        let violations = validate_event(&mut event, &config(ValidationMode::Truncate)).unwrap();
+
+        assert!(!violations.is_empty());
+        assert_eq!(event.name.len(), 255);
+        let props = event.properties.as_object().unwrap();
+        assert_eq!(props.len(), 255);
+        assert_eq!(props["distinct_id"], json!("user"));
+        assert_eq!(props["title"].as_str().unwrap().len(), 254);
+        assert_eq!(props["tags"][0].as_str().unwrap().len(), 255);
+        assert_eq!(props["$current_url"].as_str().unwrap().len(), 1000);
+        assert_eq!(props["views"], json!(u64::MAX.to_string()));
+    }
+
+    #[test]
+    fn test_pass_through_leaves_event_alone() {
+        let mut event = Event::new("viewed").property("title", "x".repeat(300));
+        let violations = validate_event(&mut event, &config(ValidationMode::PassThrough)).unwrap();
+        assert!(violations.is_empty());
+        assert_eq!(event.properties["title"].as_str().unwrap().len(), 300);
+    }
+}
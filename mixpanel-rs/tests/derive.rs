@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use mixpanel_rs::transport::RecordingTransport;
+use mixpanel_rs::types::Config;
+use mixpanel_rs::{Mixpanel, MixpanelEvent};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct VideoContext {
+    publisher_user_id: String,
+    is_nsfw: bool,
+}
+
+#[derive(MixpanelEvent)]
+#[mixpanel(name = "video_impression")]
+struct VideoImpressionEventData {
+    #[mixpanel(distinct_id)]
+    principal: String,
+    #[mixpanel(device_id)]
+    device: Option<String>,
+    #[mixpanel(time)]
+    viewed_at: DateTime<Utc>,
+    #[mixpanel(rename = "video_id")]
+    post_id: String,
+    #[mixpanel(flatten)]
+    context: VideoContext,
+    #[mixpanel(skip)]
+    #[allow(dead_code)]
+    cached: bool,
+    share_count: u32,
+}
+
+#[derive(MixpanelEvent)]
+struct HTTPSignupCompleted {
+    r#type: String,
+}
+
+fn impression() -> VideoImpressionEventData {
+    VideoImpressionEventData {
+        principal: "user-1".to_string(),
+        device: None,
+        viewed_at: DateTime::from_timestamp_millis(1_700_000_000_123).unwrap(),
+        post_id: "v1".to_string(),
+        context: VideoContext {
+            publisher_user_id: "creator".to_string(),
+            is_nsfw: false,
+        },
+        cached: true,
+        share_count: 2,
+    }
+}
+
+#[test]
+fn test_derive_maps_fields_to_properties() {
+    let event = impression().to_event().unwrap();
+
+    assert_eq!(event.name, "video_impression");
+    assert_eq!(
+        event.properties,
+        json!({
+            "distinct_id": "user-1",
+            "time": 1_700_000_000_123i64,
+            "video_id": "v1",
+            "publisher_user_id": "creator",
+            "is_nsfw": false,
+            "share_count": 2,
+        })
+    );
+}
+
+#[test]
+fn test_derive_default_name_is_snake_case() {
+    let event = HTTPSignupCompleted {
+        r#type: "google".to_string(),
+    };
+    assert_eq!(HTTPSignupCompleted::NAME, "http_signup_completed");
+    assert_eq!(
+        event.to_event().unwrap().properties,
+        json!({ "type": "google" })
+    );
+}
+
+#[tokio::test]
+async fn test_track_typed_sends_event() {
+    let transport = RecordingTransport::new();
+    let config = Config::builder()
+        .transport(Arc::new(transport.clone()))
+        .build()
+        .unwrap();
+    let mixpanel = Mixpanel::init("token", Some(config));
+
+    let event = VideoImpressionEventData {
+        viewed_at: Utc::now(),
+        ..impression()
+    };
+    mixpanel.track_typed(&event).await.unwrap();
+
+    let body = transport.requests()[0].json().unwrap();
+    assert_eq!(body[0]["event"], json!("video_impression"));
+    assert_eq!(body[0]["properties"]["video_id"], json!("v1"));
+    assert_eq!(body[0]["properties"]["token"], json!("token"));
+}
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
-use google_cloud_pubsub::publisher::Publisher;
-
 use crate::{
-    application::services, config::Config,
-    infrastructure::repository::mixpanel_repository::MixpanelRepository,
+    application::services::{self, event_router::EventRouter},
+    config::Config,
+    infrastructure::repository::{
+        event_queue::WalEventQueue, mixpanel_repository::MixpanelRepository,
+        privacy_store::FilePrivacyStore,
+    },
 };
 
 #[derive(Clone)]
@@ -12,8 +14,9 @@ pub struct AppState {
     pub config: Config,
     pub analytics_service:
         Arc<services::mixpanel_analytics_service::MixpanelService<MixpanelRepository>>,
-    pub bigquery_client: google_cloud_bigquery::client::Client,
-    pub pubsub_client: Arc<google_cloud_pubsub::client::Client>,
+    pub event_router: Arc<EventRouter>,
+    pub event_queue: Arc<WalEventQueue>,
     pub ip_client: Option<Arc<crate::ip_config::IpConfig>>,
-    pub pubsub_event_publisher: Arc<Publisher>,
+    pub privacy_service:
+        Arc<services::privacy_service::PrivacyService<MixpanelRepository, FilePrivacyStore>>,
 }
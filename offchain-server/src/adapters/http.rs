@@ -6,29 +6,36 @@ use axum::{
     Json, Router,
 };
 use candid::Principal;
-use chrono::{DateTime, Utc};
-use google_cloud_bigquery::http::tabledata::insert_all::{InsertAllRequest, Row};
 use http::HeaderMap;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 use tokio::net;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use woothee::parser::Parser;
 
 use super::{
-    app_state::AppState, auth_middleware::AuthenticatedRequest,
+    app_state::AppState, auth_middleware::AuthenticatedRequest, ingest,
     sentry_webhook::sentry_webhook_handler,
 };
 use crate::{
-    adapters::location_from_ip::insert_ip_details,
-    application::services::mixpanel_analytics_service,
+    application::services::{
+        event_router::EventRouter, mixpanel_analytics_service, privacy_service::PrivacyService,
+    },
     config::Config,
-    consts::{self, DEFAULT_OS},
-    domain::errors::AppError,
-    infrastructure::repository::mixpanel_repository::MixpanelRepository,
+    domain::{
+        errors::AppError,
+        ports::{
+            analytics::{EventSource, SinkStats},
+            privacy::DeletionRequest,
+            queue::{EventQueue, QueueStats},
+        },
+    },
+    infrastructure::repository::{
+        event_queue::WalEventQueue, mixpanel_repository::MixpanelRepository,
+        privacy_store::FilePrivacyStore,
+    },
     ip_config::{IpRange, IpRangeV2},
-    utils::{classify_device, fetch_ip_details, fetch_ip_details_v2},
+    utils::{fetch_ip_details, fetch_ip_details_v2},
 };
 use axum::extract::ConnectInfo;
 
@@ -47,8 +54,9 @@ impl HttpServer {
         config: HttpServerConfig<'_>,
         env_config: Config,
         analytics_service: mixpanel_analytics_service::MixpanelService<MixpanelRepository>,
-        bigquery_client: google_cloud_bigquery::client::Client,
-        pubsub_client: google_cloud_pubsub::client::Client,
+        privacy_service: PrivacyService<MixpanelRepository, FilePrivacyStore>,
+        event_router: EventRouter,
+        event_queue: WalEventQueue,
         ip_client: Option<crate::ip_config::IpConfig>,
     ) -> anyhow::Result<Self> {
         let trace_layer =
@@ -57,35 +65,15 @@ impl HttpServer {
                 tracing::info_span!("http_request", method = ?request.method(), uri)
             });
 
-        // --- Create Pub/Sub Publisher once ---
-        let pubsub_topic_name = consts::PUBSUB_TOPIC_NAME; // The topic you want to publish to
-        let pubsub_topic = pubsub_client.topic(pubsub_topic_name);
-
-        // Optional: Ensure topic exists on startup
-        if !pubsub_topic.exists(None).await? {
-            tracing::warn!(
-                "Pub/Sub topic '{}' does not exist. Attempting to create it.",
-                pubsub_topic_name
-            );
-            pubsub_topic.create(None, None).await.with_context(|| {
-                format!("Failed to create Pub/Sub topic '{}'", pubsub_topic_name)
-            })?;
-            tracing::info!(
-                "Successfully created Pub/Sub topic '{}'.",
-                pubsub_topic_name
-            );
-        }
-
-        let pubsub_event_publisher = Arc::new(pubsub_topic.new_publisher(None)); // Create it ONCE
-
         let state = AppState {
             config: env_config,
-            bigquery_client,
-            pubsub_event_publisher,
-            pubsub_client: Arc::new(pubsub_client),
             analytics_service: Arc::new(analytics_service),
+            privacy_service: Arc::new(privacy_service),
+            event_router: Arc::new(event_router),
+            event_queue: Arc::new(event_queue),
             ip_client: ip_client.map(Arc::new),
         };
+        ingest::spawn_workers(state.clone(), state.config.ingest_workers);
 
         let router = Router::new()
             .route("/health", get(health_route))
@@ -131,6 +119,8 @@ fn api_routes() -> Router<AppState> {
         .route("/send_event", post(send_event_to_mixpanel))
         .route("/send_bigquery", post(send_event_to_bigquery))
         .route("/sentry", post(sentry_webhook_handler))
+        .route("/privacy/delete/{principal}", post(delete_user_data))
+        .route("/admin/queue", get(get_queue_stats))
 }
 
 #[derive(serde::Serialize)]
@@ -160,13 +150,6 @@ async fn fetch_sats_balance(Path(principal): Path<Principal>) -> Result<Json<Bal
     }
 }
 
-#[derive(Serialize)]
-struct BigQueryEvent {
-    event: String,
-    params: String,
-    timestamp: String,
-}
-
 /// Structure for individual event data within a bulk event
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct EventData {
@@ -178,6 +161,9 @@ struct EventData {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct EventRow {
     event_data: EventData,
+    /// When the client recorded the event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Value>,
 }
 
 /// Structure for bulk events payload from mobile clients
@@ -201,51 +187,29 @@ enum EventPayload {
     Single(Value),
 }
 
+/// Response to an ingest request; delivery happens in the background.
+#[derive(Serialize)]
+struct Accepted {
+    queued: usize,
+}
+
 async fn send_event_to_mixpanel(
     _: AuthenticatedRequest,
     State(state): State<AppState>,
     Json(payload): Json<Value>,
-) -> Result<(), AppError> {
-    let mut payload = payload;
-    let ip_state = state.clone();
-    let analytics = state.analytics_service;
-    let principal = analytics.set_user(&mut payload).await?;
-    let event = payload
-        .get("event")
-        .and_then(|f| f.as_str())
-        .map(str::to_owned)
-        .unwrap_or("unknown".into());
-    let user_agent = payload
-        .get("user_agent")
-        .and_then(|f| f.as_str())
-        .map(str::to_owned);
-    let canister_id = payload
-        .get("canister_id")
-        .and_then(|f| f.as_str())
-        .map(str::to_owned);
-    if let Some(ua_lc) = user_agent {
-        let parser = Parser::new();
-        let os = parser.parse(&ua_lc).map(|f| f.os).unwrap_or(DEFAULT_OS);
-        payload["$os"] = os.into();
-        payload["device"] = classify_device(&ua_lc).into();
-    }
-    if let Ok(bal) = crate::utils::btc_balance_of(principal).await {
-        // Keep raw e8s value for backwards compatibility
-        payload["btc_balance_e8s"] = (bal as f64).into();
-        // Add converted BTC value (1 BTC = 100,000,000 e8s)
-        let btc_balance = (bal as f64) / 100_000_000.0;
-        payload["btc_balance"] = btc_balance.into();
-    }
-    if let Ok(bal) = crate::utils::sats_balance_of(principal).await {
-        payload["sats_balance"] = (bal).into();
+) -> Result<(StatusCode, Json<Accepted>), AppError> {
+    if state.privacy_service.is_suppressed(&payload).await {
+        tracing::info!("Dropping event for a user who requested deletion");
+        return Ok((StatusCode::ACCEPTED, Json(Accepted { queued: 0 })));
     }
-    if let Some(canister_id) = canister_id.map(|f| Principal::from_text(f).ok()).flatten() {
-        if let Ok(is_creator) = crate::utils::is_creator(principal, canister_id).await {
-            payload["is_creator"] = (is_creator).into();
-        }
-    }
-    analytics.send(&event, payload.clone()).await?;
-    send_to_bigquery(&ip_state, payload).await
+    // The worker cannot attribute an event without a principal, so reject it
+    // now while the client can still fix it.
+    let principal = payload
+        .get("principal")
+        .and_then(|f| f.as_str())
+        .ok_or_else(|| AppError::InvalidData("Missing `principal` key".to_string()))?;
+    Principal::from_text(principal)?;
+    enqueue(&state, EventSource::SendEvent, vec![payload]).await
 }
 
 async fn send_event_to_bigquery(
@@ -253,7 +217,7 @@ async fn send_event_to_bigquery(
     headers: HeaderMap,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(payload): Json<EventPayload>,
-) -> Result<(), AppError> {
+) -> Result<(StatusCode, Json<Accepted>), AppError> {
     // Extract IP address from headers if not present
     let client_ip = headers
         .get("x-forwarded-for")
@@ -262,52 +226,46 @@ async fn send_event_to_bigquery(
         .map(|s| s.trim().to_string())
         .unwrap_or_else(|| addr.ip().to_string());
 
-    match payload {
+    let events = match payload {
         EventPayload::Bulk(bulk_payload) => {
             // Handle nested bulk event structure from mobile team
             let common_fields = bulk_payload.common_fields;
 
             // Process each event in rows, merging with common fields
-            let futures = bulk_payload.rows.iter().map(|row| {
-                // Merge common fields with event fields (event fields take precedence)
-                let mut merged = common_fields.clone();
-
-                // Add IP address if not present in common fields
-                merged
-                    .entry("ip_addr".to_string())
-                    .or_insert_with(|| Value::String(client_ip.clone()));
-
-                // Extend with event-specific fields
-                merged.extend(row.event_data.fields.clone());
+            bulk_payload
+                .rows
+                .into_iter()
+                .map(|row| {
+                    // Merge common fields with event fields (event fields take precedence)
+                    let mut merged = common_fields.clone();
+
+                    // Add IP address if not present in common fields
+                    merged
+                        .entry("ip_addr".to_string())
+                        .or_insert_with(|| Value::String(client_ip.clone()));
 
-                tracing::info!("Inserting single row  from bulk data {merged:?}",);
+                    // Keep the client time of the row, it tells repeated events apart
+                    if let Some(timestamp) = row.timestamp {
+                        merged.insert("timestamp".to_string(), timestamp);
+                    }
 
-                send_to_bigquery(&state, Value::Object(merged.into_iter().collect()))
-            });
+                    // Extend with event-specific fields
+                    merged.extend(row.event_data.fields);
 
-            let results: Vec<_> = futures::future::join_all(futures).await;
-            for res in results {
-                res?;
-            }
-            Ok(())
+                    Value::Object(merged.into_iter().collect())
+                })
+                .collect()
         }
-        EventPayload::Array(events) => {
+        EventPayload::Array(mut events) => {
             // Handle array of events
-            tracing::info!("Recieved Array of events from bulk data {events:?}",);
-            let futures = events.into_iter().map(|mut event| {
+            for event in &mut events {
                 // Add IP address if not present
                 if let Some(obj) = event.as_object_mut() {
                     obj.entry("ip_addr".to_string())
                         .or_insert_with(|| Value::String(client_ip.clone()));
                 }
-                send_to_bigquery(&state, event)
-            });
-
-            let results: Vec<_> = futures::future::join_all(futures).await;
-            for res in results {
-                res?;
             }
-            Ok(())
+            events
         }
         EventPayload::Single(mut event) => {
             // Handle single event
@@ -315,80 +273,53 @@ async fn send_event_to_bigquery(
                 obj.entry("ip_addr".to_string())
                     .or_insert_with(|| Value::String(client_ip.clone()));
             }
-            tracing::info!("Recieved single payload from bulk data {event:?}",);
-            send_to_bigquery(&state, event).await
+            vec![event]
         }
-    }
-}
-async fn send_to_bigquery(state: &AppState, mut payload: Value) -> Result<(), AppError> {
-    let ip = payload
-        .get("ip_addr")
-        .and_then(|f| f.as_str())
-        .map(str::to_owned);
-    let event = payload
-        .get("event")
-        .and_then(|f| f.as_str())
-        .map(str::to_owned)
-        .unwrap_or("unknown".into());
-    if let Some(ip) = ip {
-        if let Ok(res) = fetch_ip_details(&state, &ip) {
-            let _ = insert_ip_details(res, &mut payload);
-        }
-    }
-    let current_timestamp: DateTime<Utc> = Utc::now();
-    let formatted_timestamp = current_timestamp.to_rfc3339();
-    let pubsub_event_data = json!({ "timestamp": formatted_timestamp, "event_data": payload, });
-    if let Ok(pubsub_message_data) =
-        serde_json::to_string(&pubsub_event_data).map(|f| f.into_bytes())
-    {
-        let mut attributes: HashMap<String, String> = HashMap::new();
-        attributes.insert("event_type".to_string(), event.clone());
-        attributes.insert("source".to_string(), "analytics_server".to_string());
-        let pubsub_message = google_cloud_googleapis::pubsub::v1::PubsubMessage {
-            data: pubsub_message_data,
-            attributes,
-            message_id: String::new(),
-            publish_time: None,
-            ordering_key: String::new(),
-        };
-        let res = state.pubsub_event_publisher.publish(pubsub_message).await;
-        match res.get().await {
-            Ok(message_id) => {
-                tracing::info!(
-                    "Successfully published Pub/Sub message with ID: {}",
-                    message_id
-                );
-            }
-            Err(e) => {
-                tracing::error!("Failed to publish Pub/Sub message: {:?}", e);
-            }
-        }
-    }
-    let payload = serde_json::to_string(&payload).unwrap();
-    let row = Row {
-        insert_id: None,
-        json: BigQueryEvent {
-            event: format!("mp_{event}"),
-            params: payload,
-            timestamp: chrono::Utc::now().to_rfc3339(),
-        },
-    };
-    let request = InsertAllRequest {
-        rows: vec![row],
-        ..Default::default()
     };
-    let res = state
-        .bigquery_client
-        .tabledata()
-        .insert(
-            "hot-or-not-feed-intelligence",
-            "analytics_335143420",
-            "test_events_analytics",
-            &request,
-        )
+    tracing::info!("Received {} events for BigQuery", events.len());
+    enqueue(&state, EventSource::SendBigquery, events).await
+}
+
+/// Writes `events` to the ingest queue, for the workers to deliver.
+async fn enqueue(
+    state: &AppState,
+    source: EventSource,
+    events: Vec<Value>,
+) -> Result<(StatusCode, Json<Accepted>), AppError> {
+    let queued = events.len();
+    state
+        .event_queue
+        .push(events.into_iter().map(|event| (source, event)).collect())
         .await?;
-    println!("BigQuery insert response: {:?}", res);
-    Ok(())
+    Ok((StatusCode::ACCEPTED, Json(Accepted { queued })))
+}
+
+/// Backlog of the ingest queue, and how each sink has fared since startup.
+#[derive(Serialize)]
+struct QueueStatus {
+    #[serde(flatten)]
+    queue: QueueStats,
+    sinks: Vec<SinkStats>,
+}
+
+async fn get_queue_stats(
+    _: AuthenticatedRequest,
+    State(state): State<AppState>,
+) -> Json<QueueStatus> {
+    Json(QueueStatus {
+        queue: state.event_queue.stats(),
+        sinks: state.event_router.stats(),
+    })
+}
+
+/// Starts a GDPR deletion for `principal` and stops ingesting their events.
+async fn delete_user_data(
+    _: AuthenticatedRequest,
+    State(state): State<AppState>,
+    Path(principal): Path<Principal>,
+) -> Result<(StatusCode, Json<DeletionRequest>), AppError> {
+    let request = state.privacy_service.delete_user(principal).await?;
+    Ok((StatusCode::ACCEPTED, Json(request)))
 }
 
 async fn get_my_ip(
@@ -463,7 +394,8 @@ mod tests {
                             "event": "video_viewed",
                             "device": "app",
                             "duration": 120
-                        }
+                        },
+                        "timestamp": "2025-11-04T13:56:59.164+00:00"
                     },
                     {
                         "event_data": {
@@ -489,6 +421,11 @@ mod tests {
                 bulk.common_fields.get("city").and_then(|v| v.as_str()),
                 Some("Mumbai")
             );
+            assert_eq!(
+                bulk.rows[0].timestamp,
+                Some(Value::from("2025-11-04T13:56:59.164+00:00"))
+            );
+            assert_eq!(bulk.rows[1].timestamp, None);
         } else {
             panic!("Expected bulk event payload");
         }
@@ -0,0 +1,435 @@
+use std::time::Duration;
+
+use candid::Principal;
+use k256::sha2::{Digest, Sha256};
+use serde_json::Value;
+use woothee::parser::Parser;
+
+use super::app_state::AppState;
+use crate::{
+    adapters::location_from_ip::insert_ip_details,
+    consts::{DEFAULT_OS, DELIVERY_RETRY_BASE_DELAY_MS, DELIVERY_RETRY_MAX_DELAY_MS},
+    domain::{
+        errors::AppError,
+        ports::{
+            analytics::{EventSource, SinkEvent},
+            queue::{EventQueue, QueuedEvent},
+        },
+    },
+    utils::{classify_device, fetch_ip_details},
+};
+
+/// Payload keys a client may set to identify an event across retries.
+const CLIENT_ID_KEYS: &[&str] = &["insert_id", "$insert_id", "event_id"];
+
+/// Payload keys holding the time the client recorded the event at.
+const CLIENT_TIME_KEYS: &[&str] = &["timestamp", "time"];
+
+/// Starts `workers` tasks that take events off the queue and deliver them.
+pub fn spawn_workers(state: AppState, workers: usize) {
+    for _ in 0..workers.max(1) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let queued = state.event_queue.next().await;
+                process(&state, &queued).await;
+                if let Err(e) = state.event_queue.ack(queued.id).await {
+                    tracing::error!("Failed to acknowledge queued event {}: {e}", queued.id);
+                }
+            }
+        });
+    }
+}
+
+/// Enriches a queued event and delivers it. The sinks that fail are retried
+/// until they succeed, and the event is only acknowledged after that, so it
+/// stays in the queue, and survives a restart, for as long as a sink is down.
+async fn process(state: &AppState, queued: &QueuedEvent) {
+    if state.privacy_service.is_suppressed(&queued.payload).await {
+        tracing::info!("Dropping event for a user who requested deletion");
+        return;
+    }
+    let insert_id = insert_id(queued);
+    let mut payload = queued.payload.clone();
+    if queued.source == EventSource::SendEvent {
+        if let Err(e) = enrich_user(state, &mut payload).await {
+            tracing::error!("Dropping event {}: {e}", queued.id);
+            return;
+        }
+    }
+    insert_location(state, &mut payload);
+    let event = SinkEvent {
+        name: payload
+            .get("event")
+            .and_then(|f| f.as_str())
+            .map(str::to_owned)
+            .unwrap_or("unknown".into()),
+        source: queued.source,
+        insert_id,
+        received_at: queued.received_at,
+        payload,
+    };
+
+    let router = &state.event_router;
+    let mut report = router.dispatch(queued.source, &event).await;
+    let mut delay = DELIVERY_RETRY_BASE_DELAY_MS;
+    loop {
+        let failed: Vec<String> = report.to_retry().map(|o| o.sink.clone()).collect();
+        if failed.is_empty() {
+            return;
+        }
+        tracing::warn!(
+            "Retrying event {} on {} in {delay} ms",
+            queued.id,
+            failed.join(", ")
+        );
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        delay = (delay * 2).min(DELIVERY_RETRY_MAX_DELAY_MS);
+        report = router.redeliver(queued.source, &failed, &event).await;
+    }
+}
+
+/// The id sinks deduplicate the event by: one the client sent, or else a
+/// hash of the payload as it was received, before it was enriched.
+///
+/// Identical events are only told apart by the client time in the payload;
+/// without one, the hash also covers the queue entry, which stays the same
+/// across delivery attempts.
+fn insert_id(queued: &QueuedEvent) -> String {
+    let payload = &queued.payload;
+    let client_id = CLIENT_ID_KEYS
+        .iter()
+        .filter_map(|key| payload.get(*key).and_then(Value::as_str))
+        .find(|id| !id.is_empty());
+    if let Some(id) = client_id {
+        return id.to_string();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(payload.to_string().as_bytes());
+    let has_client_time = CLIENT_TIME_KEYS
+        .iter()
+        .any(|key| payload.get(*key).is_some_and(|time| !time.is_null()));
+    if !has_client_time {
+        hasher.update(queued.id.to_be_bytes());
+        hasher.update(queued.received_at.to_rfc3339().as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Identifies the user of a `/send_event` payload and adds their device,
+/// balances and creator status.
+async fn enrich_user(state: &AppState, payload: &mut Value) -> Result<(), AppError> {
+    let principal = state.analytics_service.set_user(payload).await?;
+    let user_agent = payload
+        .get("user_agent")
+        .and_then(|f| f.as_str())
+        .map(str::to_owned);
+    let canister_id = payload
+        .get("canister_id")
+        .and_then(|f| f.as_str())
+        .map(str::to_owned);
+    if let Some(ua_lc) = user_agent {
+        let parser = Parser::new();
+        let os = parser.parse(&ua_lc).map(|f| f.os).unwrap_or(DEFAULT_OS);
+        payload["$os"] = os.into();
+        payload["device"] = classify_device(&ua_lc).into();
+    }
+    if let Ok(bal) = crate::utils::btc_balance_of(principal).await {
+        // Keep raw e8s value for backwards compatibility
+        payload["btc_balance_e8s"] = (bal as f64).into();
+        // Add converted BTC value (1 BTC = 100,000,000 e8s)
+        let btc_balance = (bal as f64) / 100_000_000.0;
+        payload["btc_balance"] = btc_balance.into();
+    }
+    if let Ok(bal) = crate::utils::sats_balance_of(principal).await {
+        payload["sats_balance"] = (bal).into();
+    }
+    if let Some(canister_id) = canister_id.and_then(|f| Principal::from_text(f).ok()) {
+        if let Ok(is_creator) = crate::utils::is_creator(principal, canister_id).await {
+            payload["is_creator"] = (is_creator).into();
+        }
+    }
+    Ok(())
+}
+
+/// Adds the city, country and region of `ip_addr`, when the payload has one.
+fn insert_location(state: &AppState, payload: &mut Value) {
+    let ip = payload
+        .get("ip_addr")
+        .and_then(|f| f.as_str())
+        .map(str::to_owned);
+    if let Some(ip) = ip {
+        if let Ok(res) = fetch_ip_details(state, &ip) {
+            let _ = insert_ip_details(res, payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        application::services::{
+            event_router::EventRouter, mixpanel_analytics_service::MixpanelService,
+            privacy_service::PrivacyService,
+        },
+        config::Config,
+        consts::DEFAULT_INGEST_WORKERS,
+        domain::ports::analytics::{EventSink, SinkFuture},
+        infrastructure::{
+            repository::{
+                event_queue::WalEventQueue, mixpanel_repository::MixpanelRepository,
+                privacy_store::FilePrivacyStore,
+            },
+            sinks::{
+                bigquery::{
+                    BatchConfig, BigQuerySink, InsertAll, InsertFuture, TableRef, TableRouting,
+                },
+                bigquery_schema::RowSchema,
+            },
+        },
+    };
+    use google_cloud_bigquery::http::tabledata::insert_all::Row;
+    use mixpanel_rs::transport::RecordingTransport;
+    use serde_json::{json, Map};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+    use tempfile::TempDir;
+
+    /// Answers every `insertAll` request after 100 ms, accepting all rows.
+    #[derive(Default)]
+    struct SlowBigQuery {
+        batches: Mutex<Vec<usize>>,
+    }
+
+    impl InsertAll for Arc<SlowBigQuery> {
+        fn insert_all<'a>(
+            &'a self,
+            _table: &'a TableRef,
+            rows: Vec<Row<Map<String, Value>>>,
+        ) -> InsertFuture<'a> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                self.batches.lock().unwrap().push(rows.len());
+                Ok(Vec::new())
+            })
+        }
+    }
+
+    /// Fails the first `failures` deliveries.
+    struct FlakySink {
+        failures: AtomicUsize,
+        delivered: AtomicUsize,
+    }
+
+    impl EventSink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        fn deliver<'a>(&'a self, _event: &'a SinkEvent) -> SinkFuture<'a> {
+            Box::pin(async move {
+                let failing = self
+                    .failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok();
+                if failing {
+                    return Err(AppError::SinkError("unavailable".to_string()));
+                }
+                self.delivered.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    fn bigquery_sink(bigquery: Arc<SlowBigQuery>) -> BigQuerySink {
+        BigQuerySink::new(
+            bigquery,
+            BatchConfig::default(),
+            TableRouting {
+                default: table(),
+                event_prefix: "mp_".to_string(),
+                environment: "test".to_string(),
+                routes: Vec::new(),
+            },
+            RowSchema {
+                columns: Vec::new(),
+                json_column: "params".to_string(),
+                keep_in_json: true,
+            },
+        )
+    }
+
+    fn table() -> TableRef {
+        TableRef {
+            project: "project".to_string(),
+            dataset: "analytics".to_string(),
+            table: "events".to_string(),
+        }
+    }
+
+    /// An app whose `/send_bigquery` events go to `sink`, with its files in
+    /// the returned directory.
+    fn state(sink: Arc<dyn EventSink>) -> (AppState, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            server_port: "3000".to_string(),
+            server_access_token: String::new(),
+            mixpanel_project_token: "token".to_string(),
+            mixpanel_region: Default::default(),
+            mixpanel_credentials: None,
+            mixpanel_id_merge: Default::default(),
+            ip_db_path: String::new(),
+            bigquery_access_key: String::new(),
+            pub_sub_access_key: String::new(),
+            privacy_store_path: dir.path().join("privacy.jsonl").display().to_string(),
+            send_event_sinks: Vec::new(),
+            send_bigquery_sinks: vec![sink.name().to_string()],
+            ingest_queue_path: dir.path().join("queue.wal").display().to_string(),
+            ingest_queue_capacity: 10_000,
+            ingest_workers: DEFAULT_INGEST_WORKERS,
+            environment: "test".to_string(),
+            bigquery_table: table(),
+            bigquery_event_prefix: "mp_".to_string(),
+            bigquery_routes: Vec::new(),
+            bigquery_columns: Vec::new(),
+            bigquery_json_column: "params".to_string(),
+            bigquery_keep_columns_in_json: true,
+        };
+        let mixpanel = MixpanelRepository::with_config(
+            "token",
+            mixpanel_rs::types::Config::builder()
+                .transport(Arc::new(RecordingTransport::new()))
+                .build()
+                .unwrap(),
+        );
+        let event_router = EventRouter::new(
+            vec![sink],
+            &[(
+                EventSource::SendBigquery,
+                config.send_bigquery_sinks.clone(),
+            )],
+        )
+        .unwrap();
+        let state = AppState {
+            analytics_service: Arc::new(MixpanelService::new(mixpanel.clone())),
+            privacy_service: Arc::new(PrivacyService::new(
+                mixpanel,
+                FilePrivacyStore::open(&config.privacy_store_path).unwrap(),
+            )),
+            event_router: Arc::new(event_router),
+            event_queue: Arc::new(
+                WalEventQueue::open(&config.ingest_queue_path, config.ingest_queue_capacity)
+                    .unwrap(),
+            ),
+            ip_client: None,
+            config,
+        };
+        (state, dir)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_workers_fill_bigquery_batches() {
+        let bigquery = Arc::new(SlowBigQuery::default());
+        let (state, _dir) = state(Arc::new(bigquery_sink(bigquery.clone())));
+        let events = (0..2_000)
+            .map(|i| {
+                let payload = json!({ "event": "video_viewed", "video_id": i });
+                (EventSource::SendBigquery, payload)
+            })
+            .collect();
+        state.event_queue.push(events).await.unwrap();
+
+        spawn_workers(state.clone(), state.config.ingest_workers);
+        while state.event_queue.stats().pending > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let batches = bigquery.batches.lock().unwrap();
+        assert_eq!(batches.iter().sum::<usize>(), 2_000);
+        // Every worker's first row makes it into the first request.
+        assert_eq!(
+            batches[0],
+            DEFAULT_INGEST_WORKERS.min(BatchConfig::default().max_rows)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_events_stay_queued_until_delivered() {
+        let sink = Arc::new(FlakySink {
+            failures: AtomicUsize::new(20),
+            delivered: AtomicUsize::new(0),
+        });
+        let (state, _dir) = state(sink.clone());
+        let payload = json!({ "event": "video_viewed" });
+        state
+            .event_queue
+            .push(vec![(EventSource::SendBigquery, payload)])
+            .await
+            .unwrap();
+
+        spawn_workers(state.clone(), 1);
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(state.event_queue.stats().pending, 1);
+        while state.event_queue.stats().pending > 0 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        assert_eq!(sink.failures.load(Ordering::SeqCst), 0);
+        assert_eq!(sink.delivered.load(Ordering::SeqCst), 1);
+    }
+
+    fn queued(id: u64, payload: Value) -> QueuedEvent {
+        QueuedEvent {
+            id,
+            source: EventSource::SendBigquery,
+            payload,
+            received_at: "2025-11-04T13:57:00Z".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_insert_id_prefers_client_id() {
+        let payload = json!({ "event": "video_viewed", "event_id": "e-1" });
+        assert_eq!(insert_id(&queued(1, payload)), "e-1");
+    }
+
+    #[test]
+    fn test_insert_id_hashes_content() {
+        let payload = json!({ "event": "video_viewed", "video_id": "v1" });
+        let other = json!({ "event": "video_viewed", "video_id": "v2" });
+
+        assert_eq!(
+            insert_id(&queued(1, payload.clone())),
+            insert_id(&queued(1, payload.clone()))
+        );
+        assert_ne!(
+            insert_id(&queued(1, payload.clone())),
+            insert_id(&queued(1, other))
+        );
+        assert_eq!(insert_id(&queued(1, payload)).len(), 64);
+    }
+
+    #[test]
+    fn test_insert_id_tells_identical_events_apart() {
+        // Without a client time, each queued event is its own row.
+        let payload = json!({ "event": "video_viewed", "video_id": "v1" });
+        assert_ne!(
+            insert_id(&queued(1, payload.clone())),
+            insert_id(&queued(2, payload))
+        );
+
+        // With one, resending the same event dedupes, a repeat does not.
+        let event = |time: &str| json!({ "event": "video_viewed", "timestamp": time });
+        let first = event("2025-11-04T13:56:59.164+00:00");
+        let repeat = event("2025-11-04T13:56:59.170+00:00");
+        assert_eq!(
+            insert_id(&queued(1, first.clone())),
+            insert_id(&queued(2, first.clone()))
+        );
+        assert_ne!(insert_id(&queued(1, first)), insert_id(&queued(1, repeat)));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod app_state;
 pub mod auth_middleware;
 pub mod http;
+pub mod ingest;
 pub mod location_from_ip;
 pub mod sentry_webhook;
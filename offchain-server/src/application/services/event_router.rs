@@ -0,0 +1,291 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::domain::{
+    errors::AppError,
+    ports::analytics::{DeliveryReport, EventSink, EventSource, SinkEvent, SinkOutcome, SinkStats},
+};
+
+#[derive(Default)]
+struct Counters {
+    delivered: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Fans events out to the sinks configured for their source, and counts how
+/// each sink fares.
+#[derive(Clone, Default)]
+pub struct EventRouter {
+    routes: HashMap<EventSource, Vec<Arc<dyn EventSink>>>,
+    counters: BTreeMap<String, Arc<Counters>>,
+}
+
+impl EventRouter {
+    /// Routes each source to the named sinks, failing on a name that none of
+    /// `sinks` has.
+    pub fn new(
+        sinks: Vec<Arc<dyn EventSink>>,
+        routes: &[(EventSource, Vec<String>)],
+    ) -> Result<Self, AppError> {
+        let mut router = Self::default();
+        for sink in &sinks {
+            router
+                .counters
+                .insert(sink.name().to_string(), Arc::default());
+        }
+        for (source, names) in routes {
+            let mut targets = Vec::new();
+            for name in names {
+                let sink = sinks
+                    .iter()
+                    .find(|sink| sink.name() == name)
+                    .ok_or_else(|| AppError::InvalidData(format!("unknown event sink {name}")))?;
+                targets.push(sink.clone());
+            }
+            router.routes.insert(*source, targets);
+        }
+        Ok(router)
+    }
+
+    /// Delivers the event to every sink of `source` concurrently. A failing
+    /// sink does not stop the others.
+    pub async fn dispatch(&self, source: EventSource, event: &SinkEvent) -> DeliveryReport {
+        self.deliver(self.sinks(source), event).await
+    }
+
+    /// Like `dispatch`, but only to the sinks of `source` named in `sinks`,
+    /// e.g. to retry the ones that failed.
+    pub async fn redeliver(
+        &self,
+        source: EventSource,
+        sinks: &[String],
+        event: &SinkEvent,
+    ) -> DeliveryReport {
+        let targets = self
+            .sinks(source)
+            .iter()
+            .filter(|sink| sinks.iter().any(|name| name == sink.name()));
+        self.deliver(targets, event).await
+    }
+
+    /// Deliveries per sink since startup, by sink name.
+    pub fn stats(&self) -> Vec<SinkStats> {
+        self.counters
+            .iter()
+            .map(|(sink, counters)| SinkStats {
+                sink: sink.clone(),
+                delivered: counters.delivered.load(Ordering::Relaxed),
+                failed: counters.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    fn sinks(&self, source: EventSource) -> &[Arc<dyn EventSink>] {
+        self.routes.get(&source).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    async fn deliver<'a>(
+        &self,
+        sinks: impl IntoIterator<Item = &'a Arc<dyn EventSink>>,
+        event: &SinkEvent,
+    ) -> DeliveryReport {
+        let deliveries = sinks.into_iter().map(|sink| async move {
+            let result = sink.deliver(event).await;
+            if let Some(counters) = self.counters.get(sink.name()) {
+                let counter = match result {
+                    Ok(()) => &counters.delivered,
+                    Err(_) => &counters.failed,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Err(e) = &result {
+                tracing::error!("Failed to deliver {} to {}: {e}", event.name, sink.name());
+            }
+            SinkOutcome {
+                sink: sink.name().to_string(),
+                best_effort: sink.best_effort(),
+                error: result.err().map(|e| e.to_string()),
+            }
+        });
+        DeliveryReport {
+            outcomes: futures::future::join_all(deliveries).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ports::analytics::SinkFuture;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn event(name: &str) -> SinkEvent {
+        SinkEvent {
+            name: name.to_string(),
+            source: EventSource::SendEvent,
+            insert_id: format!("{name}-1"),
+            received_at: chrono::Utc::now(),
+            payload: json!({}),
+        }
+    }
+
+    struct FakeSink {
+        name: &'static str,
+        fail: bool,
+        best_effort: bool,
+        delivered: Mutex<Vec<String>>,
+    }
+
+    impl FakeSink {
+        fn new(name: &'static str, fail: bool) -> Arc<Self> {
+            Self::with_best_effort(name, fail, false)
+        }
+
+        fn with_best_effort(name: &'static str, fail: bool, best_effort: bool) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                fail,
+                best_effort,
+                delivered: Mutex::default(),
+            })
+        }
+    }
+
+    impl EventSink for FakeSink {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn best_effort(&self) -> bool {
+            self.best_effort
+        }
+
+        fn deliver<'a>(&'a self, event: &'a SinkEvent) -> SinkFuture<'a> {
+            Box::pin(async move {
+                self.delivered.lock().unwrap().push(event.name.clone());
+                if self.fail {
+                    return Err(AppError::InvalidData("unavailable".to_string()));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_reports_each_sink() {
+        let mixpanel = FakeSink::new("mixpanel", false);
+        let bigquery = FakeSink::new("bigquery", true);
+        let router = EventRouter::new(
+            vec![mixpanel.clone() as Arc<dyn EventSink>, bigquery.clone()],
+            &[
+                (
+                    EventSource::SendEvent,
+                    vec!["mixpanel".to_string(), "bigquery".to_string()],
+                ),
+                (EventSource::SendBigquery, vec!["bigquery".to_string()]),
+            ],
+        )
+        .unwrap();
+
+        let report = router
+            .dispatch(EventSource::SendEvent, &event("video_viewed"))
+            .await;
+        router
+            .dispatch(EventSource::SendBigquery, &event("video_liked"))
+            .await;
+
+        assert!(!report.is_success());
+        let failed: Vec<_> = report
+            .failed()
+            .map(|outcome| outcome.sink.as_str())
+            .collect();
+        assert_eq!(failed, ["bigquery"]);
+        assert!(matches!(report.into_result(), Err(AppError::SinkError(_))));
+        assert_eq!(*mixpanel.delivered.lock().unwrap(), ["video_viewed"]);
+        assert_eq!(
+            *bigquery.delivered.lock().unwrap(),
+            ["video_viewed", "video_liked"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redeliver_only_named_sinks() {
+        let mixpanel = FakeSink::new("mixpanel", false);
+        let pubsub = FakeSink::new("pubsub", false);
+        let router = EventRouter::new(
+            vec![mixpanel.clone() as Arc<dyn EventSink>, pubsub.clone()],
+            &[(
+                EventSource::SendEvent,
+                vec!["mixpanel".to_string(), "pubsub".to_string()],
+            )],
+        )
+        .unwrap();
+
+        let report = router
+            .redeliver(
+                EventSource::SendEvent,
+                &["pubsub".to_string()],
+                &event("video_viewed"),
+            )
+            .await;
+
+        assert!(report.is_success());
+        assert_eq!(report.outcomes.len(), 1);
+        assert!(mixpanel.delivered.lock().unwrap().is_empty());
+        assert_eq!(*pubsub.delivered.lock().unwrap(), ["video_viewed"]);
+    }
+
+    #[tokio::test]
+    async fn test_best_effort_failures_are_counted_but_not_retried() {
+        let mixpanel = FakeSink::new("mixpanel", false);
+        let pubsub = FakeSink::with_best_effort("pubsub", true, true);
+        let router = EventRouter::new(
+            vec![mixpanel as Arc<dyn EventSink>, pubsub],
+            &[(
+                EventSource::SendEvent,
+                vec!["mixpanel".to_string(), "pubsub".to_string()],
+            )],
+        )
+        .unwrap();
+
+        let report = router
+            .dispatch(EventSource::SendEvent, &event("video_viewed"))
+            .await;
+        router
+            .dispatch(EventSource::SendEvent, &event("video_liked"))
+            .await;
+
+        assert_eq!(report.failed().count(), 1);
+        assert_eq!(report.to_retry().count(), 0);
+        assert_eq!(
+            router.stats(),
+            [
+                SinkStats {
+                    sink: "mixpanel".to_string(),
+                    delivered: 2,
+                    failed: 0,
+                },
+                SinkStats {
+                    sink: "pubsub".to_string(),
+                    delivered: 0,
+                    failed: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_sink_is_rejected() {
+        let result = EventRouter::new(
+            vec![FakeSink::new("mixpanel", false) as Arc<dyn EventSink>],
+            &[(EventSource::SendEvent, vec!["kafka".to_string()])],
+        );
+        assert!(matches!(result, Err(AppError::InvalidData(_))));
+    }
+}
@@ -15,7 +15,4 @@ impl<R: AnalyticsRepository> MixpanelService<R> {
     pub async fn set_user(&self, payload: &mut Value) -> Result<Principal, AppError> {
         self.repo.set_user(payload).await
     }
-    pub async fn send(&self, event: &str, payload: Value) -> Result<(), AppError> {
-        self.repo.send(event, payload).await
-    }
 }
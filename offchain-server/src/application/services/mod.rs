@@ -1,2 +1,4 @@
+pub mod event_router;
 pub mod mixpanel_analytics_service;
+pub mod privacy_service;
 pub mod sentry_service;
@@ -0,0 +1,137 @@
+use candid::Principal;
+use chrono::Utc;
+use serde_json::Value;
+
+use crate::domain::{
+    errors::AppError,
+    ports::{
+        analytics::AnalyticsRepository,
+        privacy::{DeletionRequest, PrivacyStore},
+    },
+};
+
+/// Payload keys that identify the user an event belongs to.
+const IDENTITY_KEYS: &[&str] = &["principal", "distinct_id", "user_id", "$user_id"];
+
+#[derive(Clone)]
+pub struct PrivacyService<R: AnalyticsRepository, S: PrivacyStore> {
+    repo: R,
+    store: S,
+}
+
+impl<R: AnalyticsRepository, S: PrivacyStore> PrivacyService<R, S> {
+    pub fn new(repo: R, store: S) -> Self {
+        Self { repo, store }
+    }
+
+    /// Stops ingesting events for `principal` and starts deleting what
+    /// Mixpanel already holds on them.
+    pub async fn delete_user(&self, principal: Principal) -> Result<DeletionRequest, AppError> {
+        let mut request = DeletionRequest {
+            principal: principal.to_text(),
+            task_id: None,
+            requested_at: Utc::now(),
+        };
+        // Suppress first, so nothing new comes in while the deletion runs
+        // and a failed deletion can be retried without losing the request.
+        self.store.record(request.clone()).await?;
+        request.task_id = Some(self.repo.delete_user(&request.principal).await?);
+        self.store.record(request.clone()).await?;
+        Ok(request)
+    }
+
+    /// Whether `payload` belongs to a user who asked for their data to be
+    /// deleted.
+    pub async fn is_suppressed(&self, payload: &Value) -> bool {
+        for key in IDENTITY_KEYS {
+            if let Some(id) = payload.get(*key).and_then(Value::as_str) {
+                if self.store.is_suppressed(id).await {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infrastructure::repository::{
+        mixpanel_repository::MixpanelRepository, privacy_store::InMemoryPrivacyStore,
+    };
+    use mixpanel_rs::{
+        transport::RecordingTransport,
+        types::{Config, Credentials},
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const PRINCIPAL: &str = "c724g-fanbu-s4a5s-t3frr-xdgtf-ntg4w-7qne3-mdh2u-id7b7-4xy63-oae";
+
+    fn service(
+        transport: &RecordingTransport,
+        store: &InMemoryPrivacyStore,
+    ) -> PrivacyService<MixpanelRepository, InMemoryPrivacyStore> {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .credentials(Credentials::service_account("sa", "secret", "42"))
+            .build()
+            .unwrap();
+        PrivacyService::new(
+            MixpanelRepository::with_config("token", config),
+            store.clone(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_records_task_and_suppresses() {
+        let transport = RecordingTransport::new();
+        transport.push_json(
+            200,
+            json!({ "status": "ok", "results": { "task_id": "t1" } }),
+        );
+        let store = InMemoryPrivacyStore::new();
+        let service = service(&transport, &store);
+
+        let request = service
+            .delete_user(Principal::from_text(PRINCIPAL).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(request.task_id.as_deref(), Some("t1"));
+        assert_eq!(store.get(PRINCIPAL), Some(request));
+        let body = transport.requests()[0].json().unwrap();
+        assert_eq!(body["distinct_ids"], json!([PRINCIPAL]));
+        assert!(
+            service
+                .is_suppressed(&json!({ "user_id": PRINCIPAL }))
+                .await
+        );
+        assert!(
+            !service
+                .is_suppressed(&json!({ "user_id": "2vxsx-fae" }))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_deletion_still_suppresses() {
+        let transport = RecordingTransport::new();
+        transport.push_response(400, r#"{"status":"error","error":"bad request"}"#);
+        let store = InMemoryPrivacyStore::new();
+        let service = service(&transport, &store);
+
+        let result = service
+            .delete_user(Principal::from_text(PRINCIPAL).unwrap())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(store.get(PRINCIPAL).unwrap().task_id, None);
+        assert!(
+            service
+                .is_suppressed(&json!({ "principal": PRINCIPAL }))
+                .await
+        );
+    }
+}
@@ -1,6 +1,15 @@
 use std::env;
 
 use anyhow::Context;
+use mixpanel_rs::types::{Credentials, IdMerge, Region};
+
+use crate::{
+    consts::DEFAULT_INGEST_WORKERS,
+    infrastructure::sinks::{
+        bigquery::{TableRef, TableRoute},
+        bigquery_schema::Column,
+    },
+};
 
 const SERVER_PORT_KEY: &str = "SERVER_PORT";
 
@@ -14,14 +23,94 @@ const GOOGLE_SA_KEY: &str = "GOOGLE_SA_KEY";
 
 const IP_DB_PATH: &str = "IP_DB_PATH";
 
+const MIXPANEL_REGION: &str = "MIXPANEL_REGION";
+
+const MIXPANEL_SERVICE_ACCOUNT_USERNAME: &str = "MIXPANEL_SERVICE_ACCOUNT_USERNAME";
+
+const MIXPANEL_SERVICE_ACCOUNT_SECRET: &str = "MIXPANEL_SERVICE_ACCOUNT_SECRET";
+
+const MIXPANEL_PROJECT_ID: &str = "MIXPANEL_PROJECT_ID";
+
+const MIXPANEL_API_SECRET: &str = "MIXPANEL_API_SECRET";
+
+const MIXPANEL_ID_MERGE: &str = "MIXPANEL_ID_MERGE";
+
+const PRIVACY_STORE_PATH: &str = "PRIVACY_STORE_PATH";
+
+const SEND_EVENT_SINKS: &str = "SEND_EVENT_SINKS";
+
+const SEND_BIGQUERY_SINKS: &str = "SEND_BIGQUERY_SINKS";
+
+const INGEST_QUEUE_PATH: &str = "INGEST_QUEUE_PATH";
+
+const INGEST_QUEUE_CAPACITY: &str = "INGEST_QUEUE_CAPACITY";
+
+const INGEST_WORKERS: &str = "INGEST_WORKERS";
+
+const APP_ENV: &str = "APP_ENV";
+
+const BIGQUERY_PROJECT: &str = "BIGQUERY_PROJECT";
+
+const BIGQUERY_DATASET: &str = "BIGQUERY_DATASET";
+
+const BIGQUERY_TABLE: &str = "BIGQUERY_TABLE";
+
+const BIGQUERY_EVENT_PREFIX: &str = "BIGQUERY_EVENT_PREFIX";
+
+const BIGQUERY_ROUTES: &str = "BIGQUERY_ROUTES";
+
+const BIGQUERY_COLUMNS: &str = "BIGQUERY_COLUMNS";
+
+const BIGQUERY_JSON_COLUMN: &str = "BIGQUERY_JSON_COLUMN";
+
+const BIGQUERY_KEEP_COLUMNS_IN_JSON: &str = "BIGQUERY_KEEP_COLUMNS_IN_JSON";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Config {
     pub server_port: String,
     pub server_access_token: String,
     pub mixpanel_project_token: String,
+    pub mixpanel_region: Region,
+    /// Used for `/import`, e.g. to backfill events from clients that were
+    /// offline for more than five days.
+    pub mixpanel_credentials: Option<Credentials>,
+    /// Must match the identity management setting of the Mixpanel project.
+    pub mixpanel_id_merge: IdMerge,
     pub ip_db_path: String,
     pub bigquery_access_key: String,
     pub pub_sub_access_key: String,
+    /// Where GDPR deletion requests are kept. Both this file and the ingest
+    /// queue must be on a persistent volume, `/data` on Fly.
+    pub privacy_store_path: String,
+    /// Sinks that `/api/send_event` delivers to, by name.
+    pub send_event_sinks: Vec<String>,
+    /// Sinks that `/api/send_bigquery` delivers to, by name.
+    pub send_bigquery_sinks: Vec<String>,
+    /// Write-ahead log of accepted events not yet delivered.
+    pub ingest_queue_path: String,
+    /// Events that may wait for delivery before the API answers 503.
+    pub ingest_queue_capacity: usize,
+    /// Events delivered at once, each by a worker task of its own.
+    pub ingest_workers: usize,
+    /// Deployment the server runs in, e.g. `production` or `staging`.
+    pub environment: String,
+    /// Where BigQuery rows go unless a route says otherwise.
+    pub bigquery_table: TableRef,
+    /// Put in front of event names in BigQuery.
+    pub bigquery_event_prefix: String,
+    /// JSON list of rules sending events to other tables by event name,
+    /// source or environment. The first that matches wins.
+    pub bigquery_routes: Vec<TableRoute>,
+    /// Payload fields stored in typed BigQuery columns of their own, none by
+    /// default; staging promotes the well-known ones. To promote fields in a
+    /// deployment, set them here while `bigquery_keep_columns_in_json` is on,
+    /// move queries from `JSON_EXTRACT(params, ...)` to the new columns, and
+    /// then turn it off.
+    pub bigquery_columns: Vec<Column>,
+    /// BigQuery column holding the rest of the payload as JSON.
+    pub bigquery_json_column: String,
+    /// Whether promoted fields stay in the JSON column as well.
+    pub bigquery_keep_columns_in_json: bool,
 }
 
 impl Config {
@@ -36,6 +125,18 @@ impl Config {
         let mixpanel_project_token =
             load_env(MIXPANEL_PROJECT_TOKEN).context("Failed to get mixpanel project token")?;
 
+        let mixpanel_region = load_env(MIXPANEL_REGION)
+            .unwrap_or("us".to_string())
+            .parse::<Region>()
+            .context("Failed to parse MIXPANEL_REGION")?;
+
+        let mixpanel_credentials = load_mixpanel_credentials();
+
+        let mixpanel_id_merge = load_env(MIXPANEL_ID_MERGE)
+            .unwrap_or("simplified".to_string())
+            .parse::<IdMerge>()
+            .context("Failed to parse MIXPANEL_ID_MERGE")?;
+
         let bigquery_access_key =
             load_env(GOOGLE_SA_KEY).context("Failed to get GOOGLE_SA_KEY project token")?;
 
@@ -44,17 +145,112 @@ impl Config {
 
         let ip_db_path = load_env(IP_DB_PATH).unwrap_or("ip_db.mmdb".to_string());
 
+        let privacy_store_path =
+            load_env(PRIVACY_STORE_PATH).unwrap_or("privacy_requests.jsonl".to_string());
+
+        let send_event_sinks = load_list(SEND_EVENT_SINKS, "mixpanel,bigquery,pubsub");
+
+        let send_bigquery_sinks = load_list(SEND_BIGQUERY_SINKS, "bigquery,pubsub");
+
+        let ingest_queue_path =
+            load_env(INGEST_QUEUE_PATH).unwrap_or("ingest_queue.wal".to_string());
+
+        let ingest_queue_capacity = load_env(INGEST_QUEUE_CAPACITY)
+            .unwrap_or("10000".to_string())
+            .parse::<usize>()
+            .context("Failed to parse INGEST_QUEUE_CAPACITY")?;
+
+        let ingest_workers = load_env(INGEST_WORKERS)
+            .unwrap_or(DEFAULT_INGEST_WORKERS.to_string())
+            .parse::<usize>()
+            .context("Failed to parse INGEST_WORKERS")?;
+
+        let environment = load_env(APP_ENV).unwrap_or("production".to_string());
+
+        let bigquery_table = TableRef {
+            project: load_env(BIGQUERY_PROJECT)
+                .unwrap_or("hot-or-not-feed-intelligence".to_string()),
+            dataset: load_env(BIGQUERY_DATASET).unwrap_or("analytics_335143420".to_string()),
+            table: load_env(BIGQUERY_TABLE).unwrap_or("test_events_analytics".to_string()),
+        };
+
+        let bigquery_event_prefix = load_env(BIGQUERY_EVENT_PREFIX).unwrap_or("mp_".to_string());
+
+        let bigquery_routes = serde_json::from_str::<Vec<TableRoute>>(
+            &load_env(BIGQUERY_ROUTES).unwrap_or("[]".to_string()),
+        )
+        .context("Failed to parse BIGQUERY_ROUTES")?;
+
+        let bigquery_columns = load_list(BIGQUERY_COLUMNS, "")
+            .iter()
+            .map(|column| column.parse::<Column>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse BIGQUERY_COLUMNS")?;
+
+        let bigquery_json_column = load_env(BIGQUERY_JSON_COLUMN).unwrap_or("params".to_string());
+
+        let bigquery_keep_columns_in_json = load_env(BIGQUERY_KEEP_COLUMNS_IN_JSON)
+            .unwrap_or("true".to_string())
+            .parse::<bool>()
+            .context("Failed to parse BIGQUERY_KEEP_COLUMNS_IN_JSON")?;
+
         Ok(Config {
             server_port,
             server_access_token,
             mixpanel_project_token,
+            mixpanel_region,
+            mixpanel_credentials,
+            mixpanel_id_merge,
             ip_db_path,
             pub_sub_access_key,
             bigquery_access_key,
+            privacy_store_path,
+            send_event_sinks,
+            send_bigquery_sinks,
+            ingest_queue_path,
+            ingest_queue_capacity,
+            ingest_workers,
+            environment,
+            bigquery_table,
+            bigquery_event_prefix,
+            bigquery_routes,
+            bigquery_columns,
+            bigquery_json_column,
+            bigquery_keep_columns_in_json,
         })
     }
 }
 
+/// Prefers a service account and falls back to the project API secret.
+fn load_mixpanel_credentials() -> Option<Credentials> {
+    let service_account = (
+        load_env(MIXPANEL_SERVICE_ACCOUNT_USERNAME),
+        load_env(MIXPANEL_SERVICE_ACCOUNT_SECRET),
+        load_env(MIXPANEL_PROJECT_ID),
+    );
+    if let (Ok(username), Ok(secret), Ok(project_id)) = service_account {
+        return Some(Credentials::service_account(
+            &username,
+            &secret,
+            &project_id,
+        ));
+    }
+    load_env(MIXPANEL_API_SECRET)
+        .ok()
+        .map(|secret| Credentials::api_secret(&secret))
+}
+
+/// Reads a comma separated list, falling back to `default` when unset.
+fn load_list(key: &str, default: &str) -> Vec<String> {
+    load_env(key)
+        .unwrap_or(default.to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
 fn load_env(key: &str) -> anyhow::Result<String> {
     env::var(key).with_context(|| format!("failed to load environment variable {}", key))
 }
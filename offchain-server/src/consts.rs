@@ -2,3 +2,24 @@ pub const BTC_LEDGER_CANISTER: &str = "mxzaz-hqaaa-aaaar-qaada-cai";
 pub const SATS_BALANCE_URL: &str = "https://yral-hot-or-not.go-bazzinga.workers.dev/balance";
 pub const DEFAULT_OS: &str = "web";
 pub const PUBSUB_TOPIC_NAME: &str = "analytics-events";
+pub const MIXPANEL_GZIP_MIN_BYTES: usize = 1024;
+/// Devices whose login is remembered before the list is reset. A reset only
+/// means a repeated, harmless identity event.
+pub const MAX_LINKED_DEVICES: usize = 100_000;
+/// Acknowledged events after which the ingest write-ahead log is rewritten
+/// without them.
+pub const WAL_COMPACT_AFTER_ACKS: usize = 10_000;
+/// Events delivered at once. Each BigQuery delivery waits for its row's
+/// batch, so this is also about how many rows a batch collects within
+/// `BIGQUERY_BATCH_MAX_DELAY_MS`.
+pub const DEFAULT_INGEST_WORKERS: usize = 128;
+/// Wait before the first retry of a failed sink; doubled for each later one.
+pub const DELIVERY_RETRY_BASE_DELAY_MS: u64 = 500;
+/// Longest wait between retries. Events are retried until they are
+/// delivered, so while a sink is down the workers hold on to their events
+/// and the queue fills up rather than dropping them.
+pub const DELIVERY_RETRY_MAX_DELAY_MS: u64 = 60_000;
+/// BigQuery recommends at most 500 rows per streaming insert request.
+pub const BIGQUERY_BATCH_MAX_ROWS: usize = 500;
+pub const BIGQUERY_BATCH_MAX_BYTES: usize = 5 * 1024 * 1024;
+pub const BIGQUERY_BATCH_MAX_DELAY_MS: u64 = 50;
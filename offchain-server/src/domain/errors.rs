@@ -25,8 +25,18 @@ pub enum AppError {
     CandidError(#[from] candid::Error),
     #[error("Bigquery error {0}")]
     BigqueryError(#[from] google_cloud_bigquery::http::error::Error),
+    #[error("Bigquery schema conflict {0}")]
+    SchemaConflict(String),
     #[error("IPConfig error {0}")]
     IpConfigError(String),
+    #[error("Privacy store error {0}")]
+    PrivacyStoreError(String),
+    #[error("Event delivery failed {0}")]
+    SinkError(String),
+    #[error("Event queue is full")]
+    QueueFull,
+    #[error("Event queue error {0}")]
+    QueueError(String),
 }
 
 impl IntoResponse for AppError {
@@ -36,6 +46,16 @@ impl IntoResponse for AppError {
                 (StatusCode::UNAUTHORIZED, self.to_string()).into_response()
             }
             AppError::InvalidData(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            AppError::PrivacyStoreError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
+            AppError::SinkError(_) => (StatusCode::BAD_GATEWAY, self.to_string()).into_response(),
+            AppError::QueueFull => {
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string()).into_response()
+            }
+            AppError::QueueError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
             AppError::ReqwestError(e) => (
                 e.status()
                     .map(|f| axum::http::StatusCode::from_u16(f.as_u16()))
@@ -1,9 +1,13 @@
 use crate::domain::errors::AppError;
 use candid::Principal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::future::Future;
+use std::{future::Future, pin::Pin};
 
 pub trait AnalyticsRepository: Send + Sync + 'static {
+    /// Identifies the user of `payload` and updates their profile. A failed
+    /// profile update is only logged, so it never costs the event itself.
     fn set_user(
         &self,
         payload: &mut Value,
@@ -13,4 +17,105 @@ pub trait AnalyticsRepository: Send + Sync + 'static {
         event: &str,
         payload: Value,
     ) -> impl Future<Output = Result<(), AppError>> + Send;
+    /// Starts erasing everything held on `distinct_id` and returns the id of
+    /// the deletion task.
+    fn delete_user(
+        &self,
+        distinct_id: &str,
+    ) -> impl Future<Output = Result<String, AppError>> + Send;
+}
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send + 'a>>;
+
+/// An enriched event on its way to the sinks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkEvent {
+    pub name: String,
+    pub source: EventSource,
+    /// Stays the same when the event is delivered again, so sinks that
+    /// deduplicate can drop the repeat.
+    pub insert_id: String,
+    /// When the event was accepted. Sinks time the event by it, so a late
+    /// delivery keeps the original time.
+    pub received_at: DateTime<Utc>,
+    pub payload: Value,
+}
+
+/// A destination that events are delivered to. The router keeps sinks as
+/// trait objects, so `deliver` returns a boxed future.
+pub trait EventSink: Send + Sync + 'static {
+    /// The name the sink is referred to by in config, e.g. `mixpanel`.
+    fn name(&self) -> &str;
+    /// Whether a failed delivery is only reported, and not retried.
+    fn best_effort(&self) -> bool {
+        false
+    }
+    fn deliver<'a>(&'a self, event: &'a SinkEvent) -> SinkFuture<'a>;
+}
+
+/// The endpoint an event came in through. Each source is routed to its own
+/// list of sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSource {
+    SendEvent,
+    SendBigquery,
+}
+
+/// How one sink handled an event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SinkOutcome {
+    pub sink: String,
+    pub best_effort: bool,
+    pub error: Option<String>,
+}
+
+/// How many events a sink has delivered and failed to deliver since startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SinkStats {
+    pub sink: String,
+    pub delivered: u64,
+    pub failed: u64,
+}
+
+/// The outcome of delivering an event to every sink of its source.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeliveryReport {
+    pub outcomes: Vec<SinkOutcome>,
+}
+
+impl DeliveryReport {
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(|outcome| outcome.error.is_none())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &SinkOutcome> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.error.is_some())
+    }
+
+    /// The failed sinks that are worth trying again: all but the best-effort
+    /// ones.
+    pub fn to_retry(&self) -> impl Iterator<Item = &SinkOutcome> {
+        self.failed().filter(|outcome| !outcome.best_effort)
+    }
+
+    /// Fails with `AppError::SinkError` naming every sink that failed.
+    pub fn into_result(self) -> Result<Self, AppError> {
+        if self.is_success() {
+            return Ok(self);
+        }
+        let failed: Vec<String> = self
+            .failed()
+            .map(|outcome| {
+                format!(
+                    "{}: {}",
+                    outcome.sink,
+                    outcome.error.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        Err(AppError::SinkError(failed.join("; ")))
+    }
 }
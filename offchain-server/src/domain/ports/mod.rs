@@ -1 +1,3 @@
 pub mod analytics;
+pub mod privacy;
+pub mod queue;
@@ -0,0 +1,21 @@
+use crate::domain::errors::AppError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// A user's request to have their analytics data erased. Once recorded, no
+/// more events are ingested for `principal`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletionRequest {
+    pub principal: String,
+    /// Mixpanel deletion task, once it has been created.
+    pub task_id: Option<String>,
+    pub requested_at: DateTime<Utc>,
+}
+
+pub trait PrivacyStore: Send + Sync + 'static {
+    /// Stores `request`, replacing any earlier one for the same principal.
+    fn record(&self, request: DeletionRequest)
+        -> impl Future<Output = Result<(), AppError>> + Send;
+    fn is_suppressed(&self, distinct_id: &str) -> impl Future<Output = bool> + Send;
+}
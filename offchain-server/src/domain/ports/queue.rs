@@ -0,0 +1,38 @@
+use crate::domain::{errors::AppError, ports::analytics::EventSource};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+
+/// An event accepted by the API and waiting for a worker to deliver it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedEvent {
+    pub id: u64,
+    pub source: EventSource,
+    pub payload: Value,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueueStats {
+    /// Events not yet delivered, including those being worked on.
+    pub pending: usize,
+    pub in_flight: usize,
+    pub capacity: usize,
+    pub oldest_received_at: Option<DateTime<Utc>>,
+}
+
+pub trait EventQueue: Send + Sync + 'static {
+    /// Adds all of `events`, or none of them with `AppError::QueueFull` when
+    /// they do not fit.
+    fn push(
+        &self,
+        events: Vec<(EventSource, Value)>,
+    ) -> impl Future<Output = Result<(), AppError>> + Send;
+    /// Waits for an event no worker has claimed yet and claims it.
+    fn next(&self) -> impl Future<Output = QueuedEvent> + Send;
+    /// Removes a claimed event for good, once it has been delivered or given
+    /// up on.
+    fn ack(&self, id: u64) -> impl Future<Output = Result<(), AppError>> + Send;
+    fn stats(&self) -> QueueStats;
+}
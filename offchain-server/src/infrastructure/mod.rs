@@ -1 +1,2 @@
 pub mod repository;
+pub mod sinks;
@@ -0,0 +1,420 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{oneshot, Notify};
+
+use crate::{
+    consts::WAL_COMPACT_AFTER_ACKS,
+    domain::{
+        errors::AppError,
+        ports::{
+            analytics::EventSource,
+            queue::{EventQueue, QueueStats, QueuedEvent},
+        },
+    },
+};
+
+/// A line of the write-ahead log.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Push { event: QueuedEvent },
+    Ack { id: u64 },
+}
+
+struct State {
+    pending: BTreeMap<u64, QueuedEvent>,
+    /// The pending events no worker has claimed yet, oldest first.
+    ready: VecDeque<u64>,
+    next_id: u64,
+    /// Room held for pushes whose events are still being written.
+    reserved: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+/// A change for the writer thread to append to the log.
+struct LogWrite {
+    change: Change,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+enum Change {
+    Push(Vec<QueuedEvent>),
+    Ack(u64),
+}
+
+/// A bounded queue whose events are appended to a write-ahead log before
+/// they are accepted, so they survive a restart. Events that were being
+/// worked on when the process stopped are delivered again.
+///
+/// The log is written by a thread of its own, off the async runtime. Pushes
+/// that arrive while it is busy are written together and share one fsync.
+pub struct WalEventQueue {
+    capacity: usize,
+    shared: Arc<Shared>,
+    writer: mpsc::Sender<LogWrite>,
+}
+
+impl WalEventQueue {
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> Result<Self, AppError> {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = BTreeMap::new();
+        let mut next_id = 0;
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path).map_err(queue_error)?);
+            for line in reader.lines() {
+                let line = line.map_err(queue_error)?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // The last line is cut short if the process died mid-write.
+                match serde_json::from_str(&line) {
+                    Ok(Record::Push { event }) => {
+                        next_id = next_id.max(event.id + 1);
+                        pending.insert(event.id, event);
+                    }
+                    Ok(Record::Ack { id }) => {
+                        pending.remove(&id);
+                    }
+                    Err(e) => tracing::warn!("Skipping unreadable event queue record: {e}"),
+                }
+            }
+        }
+        if !pending.is_empty() {
+            tracing::info!("Recovered {} queued events", pending.len());
+        }
+        let file = rewrite(&path, pending.values())?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                ready: pending.keys().copied().collect(),
+                pending,
+                next_id,
+                reserved: 0,
+            }),
+            notify: Notify::new(),
+        });
+        let (writer, writes) = mpsc::channel();
+        let log = Log {
+            path,
+            file,
+            acks_since_compaction: 0,
+        };
+        let writer_shared = shared.clone();
+        thread::Builder::new()
+            .name("event-queue-wal".to_string())
+            .spawn(move || log.run(&writer_shared, writes))
+            .map_err(queue_error)?;
+        Ok(Self {
+            capacity,
+            shared,
+            writer,
+        })
+    }
+
+    /// Hands `change` to the writer thread and waits until it is in the log.
+    async fn write(&self, change: Change) -> Result<(), AppError> {
+        let (done, written) = oneshot::channel();
+        self.writer
+            .send(LogWrite { change, done })
+            .map_err(|_| AppError::QueueError("event queue writer stopped".to_string()))?;
+        written
+            .await
+            .map_err(|_| AppError::QueueError("event queue writer stopped".to_string()))?
+            .map_err(AppError::QueueError)
+    }
+}
+
+impl EventQueue for WalEventQueue {
+    async fn push(&self, events: Vec<(EventSource, Value)>) -> Result<(), AppError> {
+        let count = events.len();
+        let events: Vec<QueuedEvent> = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.pending.len() + state.reserved + count > self.capacity {
+                return Err(AppError::QueueFull);
+            }
+            let first_id = state.next_id;
+            state.next_id = first_id + count as u64;
+            state.reserved += count;
+            events
+                .into_iter()
+                .zip(first_id..)
+                .map(|((source, payload), id)| QueuedEvent {
+                    id,
+                    source,
+                    payload,
+                    received_at: Utc::now(),
+                })
+                .collect()
+        };
+        // The writer makes the events claimable once they are on disk.
+        self.write(Change::Push(events)).await
+    }
+
+    async fn next(&self) -> QueuedEvent {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                // Skips events acknowledged without being claimed.
+                while let Some(id) = state.ready.pop_front() {
+                    if let Some(event) = state.pending.get(&id) {
+                        return event.clone();
+                    }
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    async fn ack(&self, id: u64) -> Result<(), AppError> {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.pending.remove(&id).is_none() {
+                return Ok(());
+            }
+        }
+        self.write(Change::Ack(id)).await
+    }
+
+    fn stats(&self) -> QueueStats {
+        let state = self.shared.state.lock().unwrap();
+        QueueStats {
+            pending: state.pending.len(),
+            in_flight: state.pending.len().saturating_sub(state.ready.len()),
+            capacity: self.capacity,
+            oldest_received_at: state.pending.values().map(|e| e.received_at).min(),
+        }
+    }
+}
+
+/// The log file, owned by the writer thread.
+struct Log {
+    path: PathBuf,
+    file: File,
+    acks_since_compaction: usize,
+}
+
+impl Log {
+    /// Appends writes until the queue is dropped, taking every write that
+    /// is waiting at once.
+    fn run(mut self, shared: &Shared, writes: mpsc::Receiver<LogWrite>) {
+        while let Ok(first) = writes.recv() {
+            let mut batch = vec![first];
+            batch.extend(writes.try_iter());
+            let result = self.append(&batch);
+
+            let mut pushed = 0;
+            let mut acks = 0;
+            {
+                let mut state = shared.state.lock().unwrap();
+                for write in &batch {
+                    match &write.change {
+                        Change::Push(events) => {
+                            state.reserved -= events.len();
+                            if result.is_ok() {
+                                pushed += events.len();
+                                state
+                                    .pending
+                                    .extend(events.iter().map(|event| (event.id, event.clone())));
+                                state.ready.extend(events.iter().map(|event| event.id));
+                            }
+                        }
+                        Change::Ack(_) => acks += 1,
+                    }
+                }
+            }
+            for _ in 0..pushed {
+                shared.notify.notify_one();
+            }
+
+            let result = result.and_then(|_| {
+                self.acks_since_compaction += acks;
+                if acks > 0 {
+                    self.compact(shared)?;
+                }
+                Ok(())
+            });
+            for write in batch {
+                let _ = write
+                    .done
+                    .send(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+            }
+        }
+    }
+
+    fn append(&mut self, batch: &[LogWrite]) -> Result<(), AppError> {
+        let mut lines = String::new();
+        let mut sync = false;
+        for write in batch {
+            match &write.change {
+                Change::Push(events) => {
+                    sync = true;
+                    for event in events {
+                        lines.push_str(&to_line(&Record::Push {
+                            event: event.clone(),
+                        })?);
+                    }
+                }
+                Change::Ack(id) => lines.push_str(&to_line(&Record::Ack { id: *id })?),
+            }
+        }
+        self.file.write_all(lines.as_bytes()).map_err(queue_error)?;
+        // A lost ack only means a repeated delivery, so acks skip the fsync.
+        if sync {
+            self.file.sync_data().map_err(queue_error)?;
+        }
+        Ok(())
+    }
+
+    /// Drops acknowledged events from the log once it is empty or enough
+    /// acks have piled up.
+    fn compact(&mut self, shared: &Shared) -> Result<(), AppError> {
+        let pending: Vec<QueuedEvent> = {
+            let state = shared.state.lock().unwrap();
+            if !state.pending.is_empty() && self.acks_since_compaction < WAL_COMPACT_AFTER_ACKS {
+                return Ok(());
+            }
+            state.pending.values().cloned().collect()
+        };
+        if pending.is_empty() {
+            self.file.set_len(0).map_err(queue_error)?;
+        } else {
+            self.file = rewrite(&self.path, pending.iter())?;
+        }
+        self.acks_since_compaction = 0;
+        Ok(())
+    }
+}
+
+/// Replaces the log at `path` with `events` and opens it for appending.
+fn rewrite<'a>(
+    path: &Path,
+    events: impl Iterator<Item = &'a QueuedEvent>,
+) -> Result<File, AppError> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut file = File::create(&tmp).map_err(queue_error)?;
+        for event in events {
+            let line = to_line(&Record::Push {
+                event: event.clone(),
+            })?;
+            file.write_all(line.as_bytes()).map_err(queue_error)?;
+        }
+        file.sync_data().map_err(queue_error)?;
+    }
+    fs::rename(&tmp, path).map_err(queue_error)?;
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(queue_error)
+}
+
+fn to_line(record: &Record) -> Result<String, AppError> {
+    let mut line =
+        serde_json::to_string(record).map_err(|e| AppError::QueueError(e.to_string()))?;
+    line.push('\n');
+    Ok(line)
+}
+
+fn queue_error(e: std::io::Error) -> AppError {
+    AppError::QueueError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_unacked_events_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.wal");
+        let queue = WalEventQueue::open(&path, 10).unwrap();
+        queue
+            .push(vec![
+                (EventSource::SendEvent, json!({ "event": "a" })),
+                (EventSource::SendBigquery, json!({ "event": "b" })),
+            ])
+            .await
+            .unwrap();
+        let first = queue.next().await;
+        queue.ack(first.id).await.unwrap();
+        // Claimed but never acknowledged, as if the worker was killed.
+        let second = queue.next().await;
+        drop(queue);
+
+        let reopened = WalEventQueue::open(&path, 10).unwrap();
+        let recovered = reopened.next().await;
+        reopened
+            .push(vec![(EventSource::SendEvent, json!({ "event": "c" }))])
+            .await
+            .unwrap();
+        let stats = reopened.stats();
+
+        assert_eq!(first.payload, json!({ "event": "a" }));
+        assert_eq!(recovered, second);
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.in_flight, 1);
+        assert_eq!(stats.oldest_received_at, Some(second.received_at));
+    }
+
+    #[tokio::test]
+    async fn test_push_is_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.wal");
+        let queue = WalEventQueue::open(&path, 2).unwrap();
+
+        let too_many = queue
+            .push(vec![
+                (EventSource::SendBigquery, json!({})),
+                (EventSource::SendBigquery, json!({})),
+                (EventSource::SendBigquery, json!({})),
+            ])
+            .await;
+        queue
+            .push(vec![(EventSource::SendBigquery, json!({}))])
+            .await
+            .unwrap();
+        let event = queue.next().await;
+        queue.ack(event.id).await.unwrap();
+        let log_len = fs::metadata(&path).unwrap().len();
+
+        assert!(matches!(too_many, Err(AppError::QueueFull)));
+        assert_eq!(queue.stats().pending, 0);
+        assert_eq!(log_len, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_pushes_are_all_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("queue.wal");
+        let queue = WalEventQueue::open(&path, 50).unwrap();
+
+        let pushes =
+            (0..60).map(|i| queue.push(vec![(EventSource::SendBigquery, json!({ "event": i }))]));
+        let results = futures::future::join_all(pushes).await;
+        drop(queue);
+        let reopened = WalEventQueue::open(&path, 50).unwrap();
+        let stats = reopened.stats();
+
+        let accepted = results.iter().filter(|result| result.is_ok()).count();
+        let full = results
+            .iter()
+            .filter(|result| matches!(result, Err(AppError::QueueFull)))
+            .count();
+        assert_eq!((accepted, full), (50, 10));
+        assert_eq!(stats.pending, 50);
+    }
+}
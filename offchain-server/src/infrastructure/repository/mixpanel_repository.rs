@@ -1,18 +1,90 @@
 use candid::Principal;
-use mixpanel_rs::Mixpanel;
+use k256::sha2::{Digest, Sha256};
+use mixpanel_rs::{
+    gdpr::ComplianceType,
+    types::{Compression, Config, Credentials, IdMerge, Region},
+    validation::{ValidationConfig, ValidationMode},
+    Mixpanel,
+};
 use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use crate::domain::{errors::AppError, ports::analytics::AnalyticsRepository};
+use crate::{
+    consts::MAX_LINKED_DEVICES,
+    domain::{
+        errors::AppError,
+        ports::analytics::{AnalyticsRepository, EventSink, SinkEvent, SinkFuture},
+    },
+};
 
 #[derive(Clone)]
 pub struct MixpanelRepository {
     mixpanel: Mixpanel,
+    /// The user each device was last linked to, so the identity event is
+    /// only sent when a device logs in as someone new.
+    linked_devices: Arc<Mutex<HashMap<String, String>>>,
 }
 
 impl MixpanelRepository {
-    pub fn new(project_token: String) -> Self {
-        let mixpanel = Mixpanel::init(&project_token, None);
-        Self { mixpanel }
+    pub fn new(
+        project_token: String,
+        region: Region,
+        credentials: Option<Credentials>,
+        id_merge: IdMerge,
+    ) -> Result<Self, AppError> {
+        let mut builder = Config::builder()
+            .region(region)
+            .id_merge(id_merge)
+            .compression(Compression::Gzip {
+                min_size: crate::consts::MIXPANEL_GZIP_MIN_BYTES,
+            })
+            // Client payloads are forwarded as-is, so fit them to Mixpanel's
+            // limits here and log what was cut rather than lose it silently.
+            .validation(ValidationConfig {
+                mode: ValidationMode::Truncate,
+                ..ValidationConfig::default()
+            });
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+        let config = builder.build()?;
+        Ok(Self::with_config(&project_token, config))
+    }
+
+    pub fn with_config(project_token: &str, config: Config) -> Self {
+        let mixpanel = Mixpanel::init(project_token, Some(config));
+        Self {
+            mixpanel,
+            linked_devices: Arc::default(),
+        }
+    }
+
+    /// Sends the identity event tying `device_id` to `user_id`, unless the
+    /// two are already linked. A failure is only logged; the link is tried
+    /// again with the device's next event.
+    async fn link_device(&self, device_id: &str, user_id: &str) {
+        let linked = self
+            .linked_devices
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .is_some_and(|linked_user| linked_user == user_id);
+        if linked {
+            return;
+        }
+        match self.mixpanel.identify(device_id, user_id).await {
+            Ok(_) => {
+                let mut linked_devices = self.linked_devices.lock().unwrap();
+                if linked_devices.len() >= MAX_LINKED_DEVICES {
+                    linked_devices.clear();
+                }
+                linked_devices.insert(device_id.to_string(), user_id.to_string());
+            }
+            Err(e) => tracing::warn!("Failed to link device {device_id} to {user_id}: {e}"),
+        }
     }
 }
 
@@ -26,25 +98,33 @@ impl AnalyticsRepository for MixpanelRepository {
             let user_id = payload
                 .get("user_id")
                 .and_then(|f| f.as_str())
+                .filter(|id| !id.is_empty())
                 .map(str::to_owned);
             let device_id = payload
                 .get("$device_id")
                 .and_then(|f| f.as_str())
+                .filter(|id| !id.is_empty())
                 .map(str::to_owned);
             let principal = Principal::from_text(principal.unwrap())?;
-            if !device_id.unwrap_or_default().is_empty() && user_id.unwrap_or_default().is_empty() {
+            if device_id.is_some() && user_id.is_none() {
                 return Ok(principal);
             }
             payload["$user_id"] = principal.to_text().as_str().into();
             payload["distinct_id"] = principal.to_text().as_str().into();
+            if let Some(device_id) = device_id {
+                self.link_device(&device_id, &principal.to_text()).await;
+            }
             let mut user_payload = payload.clone();
             user_payload["$ip"] = payload["ip"].clone();
             let ip = payload["ip"].clone();
-            let _ = self
+            let update = self
                 .mixpanel
                 .people
                 .set(principal.to_text().as_str(), ip, user_payload)
-                .await?;
+                .await;
+            if let Err(e) = update {
+                tracing::warn!("Failed to update the profile of {principal}: {e}");
+            }
             Ok(principal)
         } else {
             Err(AppError::InvalidData("Missing `principal` key".to_string()))
@@ -55,4 +135,191 @@ impl AnalyticsRepository for MixpanelRepository {
         let _ = self.mixpanel.track(event, Some(body)).await?;
         Ok(())
     }
+
+    async fn delete_user(&self, distinct_id: &str) -> Result<String, AppError> {
+        let task_id = self
+            .mixpanel
+            .gdpr
+            .create_deletion(&[distinct_id], ComplianceType::Gdpr)
+            .await?;
+        Ok(task_id)
+    }
+}
+
+impl EventSink for MixpanelRepository {
+    fn name(&self) -> &str {
+        "mixpanel"
+    }
+
+    fn deliver<'a>(&'a self, event: &'a SinkEvent) -> SinkFuture<'a> {
+        let mut payload = event.payload.clone();
+        if let Some(properties) = payload.as_object_mut() {
+            properties
+                .entry("$insert_id")
+                .or_insert_with(|| mixpanel_insert_id(&event.insert_id).into());
+            properties
+                .entry("time")
+                .or_insert_with(|| event.received_at.timestamp_millis().into());
+        }
+        Box::pin(async move { self.send(&event.name, payload).await })
+    }
+}
+
+/// Mixpanel only takes an `$insert_id` of up to 36 letters, digits and
+/// dashes, so other ids are replaced by a hash of them.
+fn mixpanel_insert_id(insert_id: &str) -> String {
+    let valid = insert_id.len() <= 36
+        && insert_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if valid {
+        return insert_id.to_string();
+    }
+    let mut hash = hex::encode(Sha256::digest(insert_id.as_bytes()));
+    hash.truncate(32);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::ports::analytics::EventSource;
+    use mixpanel_rs::transport::RecordingTransport;
+    use serde_json::json;
+
+    const PRINCIPAL: &str = "c724g-fanbu-s4a5s-t3frr-xdgtf-ntg4w-7qne3-mdh2u-id7b7-4xy63-oae";
+
+    fn repository(transport: &RecordingTransport) -> MixpanelRepository {
+        let config = Config::builder()
+            .transport(Arc::new(transport.clone()))
+            .id_merge(IdMerge::Simplified)
+            .build()
+            .unwrap();
+        MixpanelRepository::with_config("token", config)
+    }
+
+    #[tokio::test]
+    async fn test_set_user_updates_profile() {
+        let transport = RecordingTransport::new();
+        let mut payload = json!({
+            "principal": PRINCIPAL,
+            "user_id": PRINCIPAL,
+            "ip": "117.255.93.228",
+            "event": "login_success"
+        });
+
+        let principal = repository(&transport).set_user(&mut payload).await.unwrap();
+
+        assert_eq!(principal.to_text(), PRINCIPAL);
+        assert_eq!(payload["distinct_id"], json!(PRINCIPAL));
+        assert_eq!(payload["$user_id"], json!(PRINCIPAL));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url, "https://api.mixpanel.com/engage?verbose=1");
+        let update = &requests[0].json().unwrap()[0];
+        assert_eq!(update["$token"], json!("token"));
+        assert_eq!(update["$distinct_id"], json!(PRINCIPAL));
+        assert_eq!(update["$ip"], json!("117.255.93.228"));
+        assert_eq!(update["$set"]["event"], json!("login_success"));
+    }
+
+    #[tokio::test]
+    async fn test_set_user_survives_failed_profile_update() {
+        let transport = RecordingTransport::new();
+        transport.push_response(400, "invalid profile update");
+        let mut payload = json!({
+            "principal": PRINCIPAL,
+            "user_id": PRINCIPAL,
+            "event": "login_success"
+        });
+
+        let principal = repository(&transport).set_user(&mut payload).await.unwrap();
+
+        assert_eq!(principal.to_text(), PRINCIPAL);
+        assert_eq!(payload["distinct_id"], json!(PRINCIPAL));
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_user_skips_anonymous_device() {
+        let transport = RecordingTransport::new();
+        let mut payload = json!({
+            "principal": PRINCIPAL,
+            "$device_id": "device-1"
+        });
+
+        repository(&transport).set_user(&mut payload).await.unwrap();
+
+        assert!(transport.requests().is_empty());
+        assert!(payload.get("distinct_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_login_links_device_once() {
+        let transport = RecordingTransport::new();
+        let repository = repository(&transport);
+        let login = json!({
+            "principal": PRINCIPAL,
+            "user_id": PRINCIPAL,
+            "$device_id": "device-1"
+        });
+
+        repository.set_user(&mut login.clone()).await.unwrap();
+        repository.set_user(&mut login.clone()).await.unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].url, "https://api.mixpanel.com/track?verbose=1");
+        let identify = &requests[0].json().unwrap()[0];
+        assert_eq!(identify["event"], json!("$identify"));
+        assert_eq!(identify["properties"]["$device_id"], json!("device-1"));
+        assert_eq!(identify["properties"]["$user_id"], json!(PRINCIPAL));
+        assert!(requests[1..]
+            .iter()
+            .all(|request| request.url.starts_with("https://api.mixpanel.com/engage")));
+    }
+
+    #[tokio::test]
+    async fn test_set_user_requires_principal() {
+        let transport = RecordingTransport::new();
+        let mut payload = json!({ "event": "login_success" });
+
+        let result = repository(&transport).set_user(&mut payload).await;
+
+        assert!(matches!(result, Err(AppError::InvalidData(_))));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_keeps_insert_id_and_time_across_attempts() {
+        let transport = RecordingTransport::new();
+        let repository = repository(&transport);
+        let received_at = chrono::Utc::now() - chrono::Duration::minutes(1);
+        let event = SinkEvent {
+            name: "video_viewed".to_string(),
+            source: EventSource::SendEvent,
+            insert_id: "a".repeat(64),
+            received_at,
+            payload: json!({ "distinct_id": PRINCIPAL }),
+        };
+
+        repository.deliver(&event).await.unwrap();
+        repository.deliver(&event).await.unwrap();
+
+        let requests = transport.requests();
+        let sent: Vec<Value> = requests
+            .iter()
+            .map(|request| request.json().unwrap()[0]["properties"].clone())
+            .collect();
+        assert_eq!(sent[0]["$insert_id"], sent[1]["$insert_id"]);
+        assert_eq!(sent[0]["$insert_id"].as_str().unwrap().len(), 32);
+        assert_eq!(sent[0]["time"], json!(received_at.timestamp_millis()));
+    }
+
+    #[test]
+    fn test_mixpanel_insert_id_keeps_valid_ids() {
+        assert_eq!(mixpanel_insert_id("e-1"), "e-1");
+        assert_ne!(mixpanel_insert_id("e_1"), "e_1");
+    }
 }
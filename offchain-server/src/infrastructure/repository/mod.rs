@@ -1 +1,3 @@
+pub mod event_queue;
 pub mod mixpanel_repository;
+pub mod privacy_store;
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use crate::domain::{
+    errors::AppError,
+    ports::privacy::{DeletionRequest, PrivacyStore},
+};
+
+/// Keeps deletion requests in memory only. Used in tests and local runs.
+#[derive(Clone, Default)]
+pub struct InMemoryPrivacyStore {
+    requests: Arc<RwLock<HashMap<String, DeletionRequest>>>,
+}
+
+impl InMemoryPrivacyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, principal: &str) -> Option<DeletionRequest> {
+        self.requests.read().unwrap().get(principal).cloned()
+    }
+
+    fn insert(&self, request: DeletionRequest) {
+        self.requests
+            .write()
+            .unwrap()
+            .insert(request.principal.clone(), request);
+    }
+}
+
+impl PrivacyStore for InMemoryPrivacyStore {
+    async fn record(&self, request: DeletionRequest) -> Result<(), AppError> {
+        self.insert(request);
+        Ok(())
+    }
+
+    async fn is_suppressed(&self, distinct_id: &str) -> bool {
+        self.requests.read().unwrap().contains_key(distinct_id)
+    }
+}
+
+/// Appends deletion requests to a JSON lines file and reloads them on
+/// startup, so suppression survives restarts. The file is written off the
+/// async runtime.
+#[derive(Clone)]
+pub struct FilePrivacyStore {
+    requests: InMemoryPrivacyStore,
+    file: Arc<Mutex<File>>,
+}
+
+impl FilePrivacyStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, AppError> {
+        let path = path.as_ref();
+        let requests = InMemoryPrivacyStore::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(path).map_err(store_error)?);
+            for line in reader.lines() {
+                let line = line.map_err(store_error)?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let request: DeletionRequest = serde_json::from_str(&line)
+                    .map_err(|e| AppError::PrivacyStoreError(e.to_string()))?;
+                requests.insert(request);
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(store_error)?;
+        Ok(Self {
+            requests,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+impl PrivacyStore for FilePrivacyStore {
+    async fn record(&self, request: DeletionRequest) -> Result<(), AppError> {
+        let mut line = serde_json::to_string(&request)
+            .map_err(|e| AppError::PrivacyStoreError(e.to_string()))?;
+        line.push('\n');
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut file = file.lock().unwrap();
+            file.write_all(line.as_bytes())?;
+            file.sync_data()
+        })
+        .await
+        .map_err(|e| AppError::PrivacyStoreError(e.to_string()))?
+        .map_err(store_error)?;
+        self.requests.insert(request);
+        Ok(())
+    }
+
+    async fn is_suppressed(&self, distinct_id: &str) -> bool {
+        self.requests.is_suppressed(distinct_id).await
+    }
+}
+
+fn store_error(e: std::io::Error) -> AppError {
+    AppError::PrivacyStoreError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[tokio::test]
+    async fn test_file_store_reloads_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("privacy.jsonl");
+        let request = DeletionRequest {
+            principal: "2vxsx-fae".to_string(),
+            task_id: Some("t1".to_string()),
+            requested_at: Utc::now(),
+        };
+
+        FilePrivacyStore::open(&path)
+            .unwrap()
+            .record(request.clone())
+            .await
+            .unwrap();
+        let reopened = FilePrivacyStore::open(&path).unwrap();
+
+        assert!(reopened.is_suppressed("2vxsx-fae").await);
+        assert!(!reopened.is_suppressed("someone-else").await);
+        assert_eq!(reopened.requests.get("2vxsx-fae"), Some(request));
+    }
+}
@@ -0,0 +1,563 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use google_cloud_bigquery::{
+    client::Client,
+    http::tabledata::insert_all::{InsertAllRequest, Row},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+use super::bigquery_schema::RowSchema;
+use crate::{
+    consts::{BIGQUERY_BATCH_MAX_BYTES, BIGQUERY_BATCH_MAX_DELAY_MS, BIGQUERY_BATCH_MAX_ROWS},
+    domain::{
+        errors::AppError,
+        ports::analytics::{EventSink, EventSource, SinkEvent, SinkFuture},
+    },
+};
+
+/// A table that rows are streamed into.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct TableRef {
+    pub project: String,
+    pub dataset: String,
+    pub table: String,
+}
+
+/// Sends the events it matches to another table. A condition that is unset
+/// matches every event, and a part of the destination that is unset is taken
+/// from the default table.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableRoute {
+    /// Event name, where `*` matches any run of characters, e.g. `video_*`.
+    pub event: Option<String>,
+    pub source: Option<EventSource>,
+    /// Deployment the server runs in, e.g. `staging`.
+    pub environment: Option<String>,
+    pub project: Option<String>,
+    pub dataset: Option<String>,
+    pub table: Option<String>,
+}
+
+impl TableRoute {
+    fn matches(&self, event: &SinkEvent, environment: &str) -> bool {
+        self.event
+            .as_deref()
+            .is_none_or(|pattern| matches_pattern(pattern, &event.name))
+            && self.source.is_none_or(|source| source == event.source)
+            && self
+                .environment
+                .as_deref()
+                .is_none_or(|env| env == environment)
+    }
+}
+
+/// Which table each event is written to, and under what name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRouting {
+    pub default: TableRef,
+    /// Put in front of the event name in the `event` column, e.g. `mp_`.
+    pub event_prefix: String,
+    pub environment: String,
+    /// Tried in order; the first route that matches wins.
+    pub routes: Vec<TableRoute>,
+}
+
+impl TableRouting {
+    pub fn table_for(&self, event: &SinkEvent) -> TableRef {
+        self.routes
+            .iter()
+            .find(|route| route.matches(event, &self.environment))
+            .map_or_else(|| self.default.clone(), |route| self.destination(route))
+    }
+
+    /// Every table that events may be written to in this environment. Routes
+    /// after one that matches every event here, and the default table, are
+    /// never reached, so their tables are left out.
+    pub fn tables(&self) -> Vec<TableRef> {
+        let mut tables = Vec::new();
+        let routes = self.routes.iter().filter(|route| {
+            route
+                .environment
+                .as_deref()
+                .is_none_or(|env| env == self.environment)
+        });
+        for route in routes {
+            let table = self.destination(route);
+            if !tables.contains(&table) {
+                tables.push(table);
+            }
+            if route.event.is_none() && route.source.is_none() {
+                return tables;
+            }
+        }
+        if !tables.contains(&self.default) {
+            tables.push(self.default.clone());
+        }
+        tables
+    }
+
+    fn destination(&self, route: &TableRoute) -> TableRef {
+        let or_default = |part: &Option<String>, default: &String| {
+            part.clone().unwrap_or_else(|| default.clone())
+        };
+        TableRef {
+            project: or_default(&route.project, &self.default.project),
+            dataset: or_default(&route.dataset, &self.default.dataset),
+            table: or_default(&route.table, &self.default.table),
+        }
+    }
+}
+
+/// When a batch of rows is sent: as soon as any limit is reached. Rows that
+/// arrive while a request is in flight make up the next batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    pub max_rows: usize,
+    pub max_bytes: usize,
+    /// How long the first row of a batch waits for others.
+    pub max_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: BIGQUERY_BATCH_MAX_ROWS,
+            max_bytes: BIGQUERY_BATCH_MAX_BYTES,
+            max_delay: Duration::from_millis(BIGQUERY_BATCH_MAX_DELAY_MS),
+        }
+    }
+}
+
+pub type InsertFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<(usize, String)>, String>> + Send + 'a>>;
+
+/// Sends one `insertAll` request. Resolves to the rows BigQuery rejected, as
+/// row index and message, or fails if the request itself did.
+pub trait InsertAll: Send + Sync + 'static {
+    fn insert_all<'a>(
+        &'a self,
+        table: &'a TableRef,
+        rows: Vec<Row<Map<String, Value>>>,
+    ) -> InsertFuture<'a>;
+}
+
+impl InsertAll for Client {
+    fn insert_all<'a>(
+        &'a self,
+        table: &'a TableRef,
+        rows: Vec<Row<Map<String, Value>>>,
+    ) -> InsertFuture<'a> {
+        Box::pin(async move {
+            let request = InsertAllRequest {
+                // Keep the valid rows of a batch when others are rejected.
+                skip_invalid_rows: Some(true),
+                rows,
+                ..Default::default()
+            };
+            let response = self
+                .tabledata()
+                .insert(&table.project, &table.dataset, &table.table, &request)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(response
+                .insert_errors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|error| (error.index as usize, format!("{:?}", error.errors)))
+                .collect())
+        })
+    }
+}
+
+/// A row waiting for its batch, and where to report how it went.
+struct PendingRow {
+    table: TableRef,
+    row: Row<Map<String, Value>>,
+    bytes: usize,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// Streams events into the analytics tables. Rows from concurrent deliveries
+/// are collected into one `insertAll` request per table, and each delivery
+/// only fails if its own row was rejected. Since every delivery waits for its
+/// row's request, a batch holds at most one row per event being delivered at
+/// once.
+#[derive(Clone)]
+pub struct BigQuerySink {
+    rows: mpsc::Sender<PendingRow>,
+    routing: Arc<TableRouting>,
+    schema: Arc<RowSchema>,
+}
+
+impl BigQuerySink {
+    /// Starts the task that writes batches through `writer`, usually the
+    /// BigQuery client.
+    pub fn new(
+        writer: impl InsertAll,
+        batch: BatchConfig,
+        routing: TableRouting,
+        schema: RowSchema,
+    ) -> Self {
+        let (rows, receiver) = mpsc::channel(batch.max_rows.max(1) * 4);
+        tokio::spawn(write_batches(writer, Batcher::new(receiver, batch)));
+        Self {
+            rows,
+            routing: Arc::new(routing),
+            schema: Arc::new(schema),
+        }
+    }
+
+    async fn insert(&self, event: &SinkEvent) -> Result<(), AppError> {
+        let json = self.schema.to_row(
+            format!("{}{}", self.routing.event_prefix, event.name),
+            event.received_at,
+            &event.payload,
+        );
+        let bytes = serde_json::to_vec(&json).map_or(0, |body| body.len());
+        let (done, result) = oneshot::channel();
+        let row = PendingRow {
+            table: self.routing.table_for(event),
+            row: Row {
+                insert_id: Some(event.insert_id.clone()),
+                json,
+            },
+            bytes,
+            done,
+        };
+        self.rows
+            .send(row)
+            .await
+            .map_err(|_| AppError::SinkError("BigQuery writer stopped".to_string()))?;
+        result
+            .await
+            .map_err(|_| AppError::SinkError("BigQuery writer stopped".to_string()))?
+            .map_err(AppError::SinkError)
+    }
+}
+
+impl EventSink for BigQuerySink {
+    fn name(&self) -> &str {
+        "bigquery"
+    }
+
+    fn deliver<'a>(&'a self, event: &'a SinkEvent) -> SinkFuture<'a> {
+        Box::pin(self.insert(event))
+    }
+}
+
+async fn write_batches(writer: impl InsertAll, mut batcher: Batcher<PendingRow>) {
+    while let Some(rows) = batcher.next().await {
+        let mut by_table: HashMap<TableRef, Vec<PendingRow>> = HashMap::new();
+        for row in rows {
+            by_table.entry(row.table.clone()).or_default().push(row);
+        }
+        let inserts = by_table
+            .into_iter()
+            .map(|(table, rows)| insert_rows(&writer, table, rows));
+        futures::future::join_all(inserts).await;
+    }
+}
+
+/// Sends `rows` to `table` in one request and reports to each row.
+async fn insert_rows(writer: &impl InsertAll, table: TableRef, rows: Vec<PendingRow>) {
+    let (rows, done): (Vec<_>, Vec<_>) = rows.into_iter().map(|p| (p.row, p.done)).unzip();
+    let results = match writer.insert_all(&table, rows).await {
+        Ok(errors) => row_results(done.len(), errors),
+        Err(e) => vec![Err(e); done.len()],
+    };
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        tracing::error!(
+            "BigQuery rejected {failed} of {} rows for {}.{}",
+            done.len(),
+            table.dataset,
+            table.table
+        );
+    }
+    for (done, result) in done.into_iter().zip(results) {
+        let _ = done.send(result);
+    }
+}
+
+/// Collects received rows into batches.
+struct Batcher<T> {
+    receiver: mpsc::Receiver<T>,
+    config: BatchConfig,
+    /// The row that did not fit into the last batch.
+    held: Option<T>,
+}
+
+impl<T: RowSize> Batcher<T> {
+    fn new(receiver: mpsc::Receiver<T>, config: BatchConfig) -> Self {
+        Self {
+            receiver,
+            config,
+            held: None,
+        }
+    }
+
+    /// Waits for a first row, then for more until a limit is reached. A row
+    /// that would take the batch over `max_bytes` starts the next one
+    /// instead. `None` once every sender is gone.
+    async fn next(&mut self) -> Option<Vec<T>> {
+        let first = match self.held.take() {
+            Some(row) => row,
+            None => self.receiver.recv().await?,
+        };
+        let deadline = Instant::now() + self.config.max_delay;
+        let mut bytes = first.size();
+        let mut rows = vec![first];
+        while rows.len() < self.config.max_rows {
+            let Ok(Some(row)) = tokio::time::timeout_at(deadline, self.receiver.recv()).await
+            else {
+                break;
+            };
+            if bytes + row.size() > self.config.max_bytes {
+                self.held = Some(row);
+                break;
+            }
+            bytes += row.size();
+            rows.push(row);
+        }
+        Some(rows)
+    }
+}
+
+/// Bytes a row adds to the request.
+trait RowSize {
+    fn size(&self) -> usize;
+}
+
+impl RowSize for PendingRow {
+    fn size(&self) -> usize {
+        self.bytes
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of
+/// characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = name.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// The result of each of `len` rows, given the `insertErrors` of the
+/// response as row index and message.
+fn row_results(len: usize, errors: Vec<(usize, String)>) -> Vec<Result<(), String>> {
+    let mut errors: HashMap<usize, String> = errors.into_iter().collect();
+    (0..len)
+        .map(|index| match errors.remove(&index) {
+            Some(message) => Err(message),
+            None => Ok(()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl RowSize for usize {
+        fn size(&self) -> usize {
+            *self
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_flush_on_count_and_size() {
+        let (sender, receiver) = mpsc::channel(10);
+        let mut batcher = Batcher::new(
+            receiver,
+            BatchConfig {
+                max_rows: 3,
+                max_bytes: 100,
+                max_delay: Duration::from_secs(1),
+            },
+        );
+        for size in [10, 10, 10, 10, 95, 10] {
+            sender.send(size).await.unwrap();
+        }
+
+        assert_eq!(batcher.next().await.unwrap(), [10, 10, 10]);
+        // A row that would take a batch over the limit starts the next one.
+        assert_eq!(batcher.next().await.unwrap(), [10]);
+        assert_eq!(batcher.next().await.unwrap(), [95]);
+        assert_eq!(batcher.next().await.unwrap(), [10]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_wait_for_rows_until_max_delay() {
+        let (sender, receiver) = mpsc::channel(10);
+        let mut batcher = Batcher::new(
+            receiver,
+            BatchConfig {
+                max_rows: 10,
+                max_bytes: 1000,
+                max_delay: Duration::from_millis(50),
+            },
+        );
+        tokio::spawn(async move {
+            for (wait, row) in [(0, 1), (20, 2), (100, 3)] {
+                tokio::time::sleep(Duration::from_millis(wait)).await;
+                sender.send(row).await.unwrap();
+            }
+        });
+
+        assert_eq!(batcher.next().await.unwrap(), [1, 2]);
+        assert_eq!(batcher.next().await.unwrap(), [3]);
+        assert!(batcher.next().await.is_none());
+    }
+
+    fn event(name: &str, source: EventSource) -> SinkEvent {
+        SinkEvent {
+            name: name.to_string(),
+            source,
+            insert_id: format!("{name}-1"),
+            received_at: chrono::Utc::now(),
+            payload: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn test_table_for_uses_first_matching_route() {
+        let routing = TableRouting {
+            default: TableRef {
+                project: "project".to_string(),
+                dataset: "analytics".to_string(),
+                table: "events".to_string(),
+            },
+            event_prefix: "mp_".to_string(),
+            environment: "staging".to_string(),
+            routes: vec![
+                TableRoute {
+                    environment: Some("production".to_string()),
+                    table: Some("prod_events".to_string()),
+                    ..Default::default()
+                },
+                TableRoute {
+                    event: Some("video_*".to_string()),
+                    source: Some(EventSource::SendBigquery),
+                    table: Some("video_events".to_string()),
+                    ..Default::default()
+                },
+                TableRoute {
+                    environment: Some("staging".to_string()),
+                    dataset: Some("analytics_staging".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let video = routing.table_for(&event("video_viewed", EventSource::SendBigquery));
+        let other = routing.table_for(&event("video_viewed", EventSource::SendEvent));
+
+        assert_eq!(video.dataset, "analytics");
+        assert_eq!(video.table, "video_events");
+        assert_eq!(other.dataset, "analytics_staging");
+        assert_eq!(other.table, "events");
+        assert_eq!(
+            TableRouting {
+                routes: Vec::new(),
+                ..routing.clone()
+            }
+            .table_for(&event("login", EventSource::SendEvent)),
+            routing.default
+        );
+    }
+
+    #[test]
+    fn test_tables_of_this_environment() {
+        let table = |table: &str| TableRef {
+            project: "project".to_string(),
+            dataset: "analytics".to_string(),
+            table: table.to_string(),
+        };
+        let routing = TableRouting {
+            default: table("events"),
+            event_prefix: String::new(),
+            environment: "staging".to_string(),
+            routes: vec![
+                TableRoute {
+                    environment: Some("production".to_string()),
+                    table: Some("prod_events".to_string()),
+                    ..Default::default()
+                },
+                TableRoute {
+                    event: Some("video_*".to_string()),
+                    table: Some("video_events".to_string()),
+                    ..Default::default()
+                },
+                TableRoute {
+                    source: Some(EventSource::SendEvent),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        assert_eq!(routing.tables(), [table("video_events"), table("events")]);
+    }
+
+    #[test]
+    fn test_staging_tables_leave_out_production() {
+        // As configured in fly-staging.toml.
+        let routing = TableRouting {
+            default: TableRef {
+                project: "hot-or-not-feed-intelligence".to_string(),
+                dataset: "analytics_335143420".to_string(),
+                table: "test_events_analytics".to_string(),
+            },
+            event_prefix: "mp_".to_string(),
+            environment: "staging".to_string(),
+            routes: serde_json::from_str(
+                r#"[{"environment": "staging", "dataset": "analytics_staging"}]"#,
+            )
+            .unwrap(),
+        };
+
+        assert_eq!(
+            routing.tables(),
+            [TableRef {
+                project: "hot-or-not-feed-intelligence".to_string(),
+                dataset: "analytics_staging".to_string(),
+                table: "test_events_analytics".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("video_viewed", "video_viewed"));
+        assert!(!matches_pattern("video_viewed", "video_viewed_2"));
+        assert!(matches_pattern("video_*", "video_viewed"));
+        assert!(matches_pattern("*_viewed", "video_viewed"));
+        assert!(matches_pattern("v*_*d", "video_viewed"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("video_*_liked", "video_viewed"));
+        assert!(!matches_pattern("*ab*ba", "aba"));
+    }
+
+    #[test]
+    fn test_row_results_fail_only_rejected_rows() {
+        let results = row_results(3, vec![(1, "invalid".to_string())]);
+        assert_eq!(results, [Ok(()), Err("invalid".to_string()), Ok(())]);
+    }
+}
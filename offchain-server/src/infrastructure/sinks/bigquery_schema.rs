@@ -0,0 +1,430 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use google_cloud_bigquery::{
+    client::Client,
+    http::{
+        error::Error,
+        table::{
+            Table, TableFieldMode, TableFieldSchema, TableFieldType, TableReference, TableSchema,
+        },
+    },
+};
+use serde_json::{Map, Value};
+
+use super::bigquery::TableRef;
+use crate::domain::errors::AppError;
+
+/// The BigQuery type of a promoted column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Timestamp,
+}
+
+impl ColumnType {
+    fn field_type(self) -> TableFieldType {
+        match self {
+            ColumnType::String => TableFieldType::String,
+            ColumnType::Integer => TableFieldType::Integer,
+            ColumnType::Float => TableFieldType::Float,
+            ColumnType::Boolean => TableFieldType::Boolean,
+            ColumnType::Timestamp => TableFieldType::Timestamp,
+        }
+    }
+
+    /// `value` as this type, or `None` if it isn't one.
+    fn convert(self, value: &Value) -> Option<Value> {
+        match (self, value) {
+            (ColumnType::String, Value::String(_)) => Some(value.clone()),
+            (ColumnType::String, Value::Number(_) | Value::Bool(_)) => {
+                Some(value.to_string().into())
+            }
+            (ColumnType::Integer, Value::Number(n)) => n
+                .as_i64()
+                .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64))
+                .map(Value::from),
+            (ColumnType::Integer, Value::String(s)) => s.parse::<i64>().ok().map(Value::from),
+            (ColumnType::Float, Value::Number(n)) => n.as_f64().map(Value::from),
+            (ColumnType::Float, Value::String(s)) => s.parse::<f64>().ok().map(Value::from),
+            (ColumnType::Boolean, Value::Bool(_)) => Some(value.clone()),
+            (ColumnType::Boolean, Value::String(s)) => s.parse::<bool>().ok().map(Value::from),
+            (ColumnType::Timestamp, Value::String(s)) => {
+                DateTime::parse_from_rfc3339(s).ok().map(|time| {
+                    time.with_timezone(&Utc)
+                        .to_rfc3339_opts(SecondsFormat::Micros, true)
+                        .into()
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "STRING" => Ok(ColumnType::String),
+            "INTEGER" | "INT64" => Ok(ColumnType::Integer),
+            "FLOAT" | "FLOAT64" => Ok(ColumnType::Float),
+            "BOOLEAN" | "BOOL" => Ok(ColumnType::Boolean),
+            "TIMESTAMP" => Ok(ColumnType::Timestamp),
+            _ => Err(AppError::InvalidData(format!("unknown column type {s}"))),
+        }
+    }
+}
+
+/// A payload field stored in a column of its own, written `field:TYPE`,
+/// e.g. `video_id:STRING`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    /// Key of the field in the event payload, e.g. `$os`.
+    pub field: String,
+    pub column_type: ColumnType,
+}
+
+impl Column {
+    /// The field as a column name: without a leading `$`, and with other
+    /// characters BigQuery does not allow replaced by `_`.
+    pub fn name(&self) -> String {
+        self.field
+            .trim_start_matches('$')
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+}
+
+impl FromStr for Column {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, column_type) = s
+            .rsplit_once(':')
+            .ok_or_else(|| AppError::InvalidData(format!("column {s} has no type")))?;
+        Ok(Column {
+            field: field.trim().to_string(),
+            column_type: column_type.trim().parse()?,
+        })
+    }
+}
+
+/// How events are laid out in table rows: the `event` and `timestamp`
+/// columns, the promoted columns, and a JSON column holding the rest of the
+/// payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowSchema {
+    pub columns: Vec<Column>,
+    pub json_column: String,
+    /// Also keep promoted fields in the JSON column, so that queries reading
+    /// them from there keep working until they move to the columns.
+    pub keep_in_json: bool,
+}
+
+impl RowSchema {
+    /// Fails if two columns would get the same name, as one would overwrite
+    /// the other in every row. BigQuery column names ignore case.
+    pub fn new(
+        columns: Vec<Column>,
+        json_column: String,
+        keep_in_json: bool,
+    ) -> Result<Self, AppError> {
+        let mut names = vec![
+            "event".to_string(),
+            "timestamp".to_string(),
+            json_column.to_ascii_lowercase(),
+        ];
+        for column in &columns {
+            let name = column.name().to_ascii_lowercase();
+            if names.contains(&name) {
+                return Err(AppError::InvalidData(format!(
+                    "column {} of field {} is already taken",
+                    column.name(),
+                    column.field
+                )));
+            }
+            names.push(name);
+        }
+        Ok(Self {
+            columns,
+            json_column,
+            keep_in_json,
+        })
+    }
+
+    /// The row of an event. A field whose value does not fit its column is
+    /// kept in the JSON column instead.
+    pub fn to_row(
+        &self,
+        event: String,
+        timestamp: DateTime<Utc>,
+        payload: &Value,
+    ) -> Map<String, Value> {
+        let mut row = Map::new();
+        row.insert("event".to_string(), event.into());
+        row.insert(
+            "timestamp".to_string(),
+            timestamp
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        let mut rest = payload.clone();
+        if let Value::Object(fields) = &mut rest {
+            for column in &self.columns {
+                let Some(value) = fields.get(&column.field) else {
+                    continue;
+                };
+                if value.is_null() {
+                    fields.remove(&column.field);
+                } else if let Some(value) = column.column_type.convert(value) {
+                    row.insert(column.name(), value);
+                    if !self.keep_in_json {
+                        fields.remove(&column.field);
+                    }
+                }
+            }
+        }
+        // Streaming inserts take JSON columns as a string.
+        row.insert(self.json_column.clone(), rest.to_string().into());
+        row
+    }
+
+    /// Every column of the table, all nullable.
+    fn fields(&self) -> Vec<TableFieldSchema> {
+        let field = |name: &str, data_type| TableFieldSchema {
+            name: name.to_string(),
+            data_type,
+            mode: Some(TableFieldMode::Nullable),
+            ..Default::default()
+        };
+        let mut fields = vec![
+            field("event", TableFieldType::String),
+            field("timestamp", TableFieldType::Timestamp),
+        ];
+        fields.extend(
+            self.columns
+                .iter()
+                .map(|column| field(&column.name(), column.column_type.field_type())),
+        );
+        fields.push(field(&self.json_column, TableFieldType::Json));
+        fields
+    }
+
+    /// Creates `table` if it does not exist, or else adds the columns it
+    /// lacks. BigQuery cannot change the type of a column, and would reject
+    /// every row with a column of another type, so those are an error.
+    pub async fn ensure_table(&self, client: &Client, table: &TableRef) -> Result<(), AppError> {
+        let mut existing = match client
+            .table()
+            .get(&table.project, &table.dataset, &table.table)
+            .await
+        {
+            Ok(existing) => existing,
+            Err(Error::Response(e)) if e.code == 404 => {
+                let created = Table {
+                    table_reference: TableReference {
+                        project_id: table.project.clone(),
+                        dataset_id: table.dataset.clone(),
+                        table_id: table.table.clone(),
+                    },
+                    schema: Some(TableSchema {
+                        fields: self.fields(),
+                    }),
+                    ..Default::default()
+                };
+                client.table().create(&created).await?;
+                tracing::info!("Created BigQuery table {}.{}", table.dataset, table.table);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let schema = existing.schema.get_or_insert_with(Default::default);
+        let (missing, conflicts) = compare_fields(&schema.fields, self.fields(), &self.json_column);
+        if !conflicts.is_empty() {
+            return Err(AppError::SchemaConflict(format!(
+                "in table {}.{}: {}",
+                table.dataset,
+                table.table,
+                conflicts.join(", ")
+            )));
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<String> = missing.iter().map(|field| field.name.clone()).collect();
+        schema.fields.extend(missing);
+        client.table().patch(&existing).await?;
+        tracing::info!(
+            "Added columns {} to BigQuery table {}.{}",
+            names.join(", "),
+            table.dataset,
+            table.table
+        );
+        Ok(())
+    }
+}
+
+/// The `expected` fields that `existing` lacks, and a message for each one it
+/// has with another type. The JSON column may also be a string, and so may
+/// `timestamp`, which older tables store as RFC 3339 text.
+fn compare_fields(
+    existing: &[TableFieldSchema],
+    expected: Vec<TableFieldSchema>,
+    json_column: &str,
+) -> (Vec<TableFieldSchema>, Vec<String>) {
+    let mut missing = Vec::new();
+    let mut conflicts = Vec::new();
+    for field in expected {
+        let Some(current) = existing.iter().find(|current| current.name == field.name) else {
+            missing.push(field);
+            continue;
+        };
+        let compatible = canonical(&current.data_type) == canonical(&field.data_type)
+            || ((field.name == json_column || field.name == "timestamp")
+                && current.data_type == TableFieldType::String);
+        if !compatible {
+            conflicts.push(format!(
+                "column {} is {:?}, expected {:?}",
+                field.name, current.data_type, field.data_type
+            ));
+        }
+    }
+    (missing, conflicts)
+}
+
+/// Legacy and standard SQL names of the same type compare equal.
+fn canonical(data_type: &TableFieldType) -> TableFieldType {
+    match data_type {
+        TableFieldType::Int64 => TableFieldType::Integer,
+        TableFieldType::Float64 => TableFieldType::Float,
+        TableFieldType::Bool => TableFieldType::Boolean,
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn columns(columns: &[&str]) -> Vec<Column> {
+        columns
+            .iter()
+            .map(|column| column.parse().unwrap())
+            .collect()
+    }
+
+    fn schema() -> RowSchema {
+        RowSchema::new(
+            columns(&["distinct_id:STRING", "$os:STRING", "sats_balance:FLOAT"]),
+            "params".to_string(),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_to_row_promotes_fields_that_fit() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let payload = json!({
+            "distinct_id": "user-1",
+            "$os": "android",
+            "sats_balance": "many",
+            "video_id": "v1",
+        });
+
+        let row = schema().to_row("mp_video_viewed".to_string(), timestamp, &payload);
+
+        assert_eq!(
+            Value::Object(row),
+            json!({
+                "event": "mp_video_viewed",
+                "timestamp": "2023-11-14T22:13:20.000000Z",
+                "distinct_id": "user-1",
+                "os": "android",
+                "params": json!({ "sats_balance": "many", "video_id": "v1" }).to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_row_can_keep_promoted_fields_in_json() {
+        let timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let payload = json!({ "distinct_id": "user-1", "video_id": "v1" });
+        let schema = RowSchema {
+            keep_in_json: true,
+            ..schema()
+        };
+
+        let row = schema.to_row("mp_video_viewed".to_string(), timestamp, &payload);
+
+        assert_eq!(row["distinct_id"], json!("user-1"));
+        assert_eq!(row["params"], json!(payload.to_string()));
+    }
+
+    #[test]
+    fn test_new_rejects_colliding_columns() {
+        let new = |list: &[&str]| RowSchema::new(columns(list), "params".to_string(), false);
+
+        assert!(new(&["$os:STRING", "os:STRING"]).is_err());
+        assert!(new(&["Event:STRING"]).is_err());
+        assert!(new(&["timestamp:TIMESTAMP"]).is_err());
+        assert!(new(&["params:STRING"]).is_err());
+        assert!(new(&["$os:STRING", "$browser:STRING"]).is_ok());
+    }
+
+    #[test]
+    fn test_column_parsing() {
+        let column: Column = "btc_balance_e8s : float64".parse().unwrap();
+
+        assert_eq!(column.field, "btc_balance_e8s");
+        assert_eq!(column.column_type, ColumnType::Float);
+        assert_eq!(ColumnType::Integer.convert(&json!(12.0)), Some(json!(12)));
+        assert!("video_id".parse::<Column>().is_err());
+        assert!("video_id:JSONB".parse::<Column>().is_err());
+    }
+
+    #[test]
+    fn test_compare_fields_adds_missing_and_reports_conflicts() {
+        let existing = vec![
+            TableFieldSchema {
+                name: "event".to_string(),
+                data_type: TableFieldType::String,
+                ..Default::default()
+            },
+            TableFieldSchema {
+                name: "timestamp".to_string(),
+                data_type: TableFieldType::String,
+                ..Default::default()
+            },
+            TableFieldSchema {
+                name: "params".to_string(),
+                data_type: TableFieldType::String,
+                ..Default::default()
+            },
+            TableFieldSchema {
+                name: "os".to_string(),
+                data_type: TableFieldType::Integer,
+                ..Default::default()
+            },
+            TableFieldSchema {
+                name: "sats_balance".to_string(),
+                data_type: TableFieldType::Float64,
+                ..Default::default()
+            },
+        ];
+
+        let (missing, conflicts) = compare_fields(&existing, schema().fields(), "params");
+
+        let missing: Vec<_> = missing.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(missing, ["distinct_id"]);
+        // A string `timestamp` takes RFC 3339 text, so only `os` conflicts.
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].starts_with("column os"));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bigquery;
+pub mod bigquery_schema;
+pub mod pubsub;
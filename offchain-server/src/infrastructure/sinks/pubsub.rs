@@ -0,0 +1,84 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Context;
+use google_cloud_googleapis::pubsub::v1::PubsubMessage;
+use google_cloud_pubsub::{client::Client, publisher::Publisher};
+use serde_json::json;
+
+use crate::domain::{
+    errors::AppError,
+    ports::analytics::{EventSink, SinkEvent, SinkFuture},
+};
+
+/// Publishes each event to a Pub/Sub topic, tagged with its event type.
+/// Publishing is best-effort: a failed publish is reported and counted, but
+/// not retried.
+#[derive(Clone)]
+pub struct PubSubSink {
+    publisher: Arc<Publisher>,
+}
+
+impl PubSubSink {
+    /// Connects to `topic_name`, creating the topic if it does not exist.
+    pub async fn connect(client: &Client, topic_name: &str) -> anyhow::Result<Self> {
+        let topic = client.topic(topic_name);
+        if !topic.exists(None).await? {
+            tracing::warn!(
+                "Pub/Sub topic '{}' does not exist. Attempting to create it.",
+                topic_name
+            );
+            topic
+                .create(None, None)
+                .await
+                .with_context(|| format!("Failed to create Pub/Sub topic '{}'", topic_name))?;
+            tracing::info!("Successfully created Pub/Sub topic '{}'.", topic_name);
+        }
+        Ok(Self {
+            publisher: Arc::new(topic.new_publisher(None)),
+        })
+    }
+
+    async fn publish(&self, event: &SinkEvent) -> Result<(), AppError> {
+        let data = json!({
+            "timestamp": event.received_at.to_rfc3339(),
+            "event_data": event.payload
+        });
+        let mut attributes: HashMap<String, String> = HashMap::new();
+        attributes.insert("event_type".to_string(), event.name.clone());
+        attributes.insert("source".to_string(), "analytics_server".to_string());
+        attributes.insert("insert_id".to_string(), event.insert_id.clone());
+        let message = PubsubMessage {
+            data: data.to_string().into_bytes(),
+            attributes,
+            message_id: String::new(),
+            publish_time: None,
+            ordering_key: String::new(),
+        };
+        let message_id = self
+            .publisher
+            .publish(message)
+            .await
+            .get()
+            .await
+            .map_err(|e| AppError::SinkError(format!("Failed to publish to Pub/Sub: {e:?}")))?;
+        tracing::info!(
+            "Successfully published Pub/Sub message with ID: {}",
+            message_id
+        );
+        Ok(())
+    }
+}
+
+impl EventSink for PubSubSink {
+    fn name(&self) -> &str {
+        "pubsub"
+    }
+
+    fn best_effort(&self) -> bool {
+        true
+    }
+
+    fn deliver<'a>(&'a self, event: &'a SinkEvent) -> SinkFuture<'a> {
+        Box::pin(self.publish(event))
+    }
+}
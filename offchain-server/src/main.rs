@@ -1,5 +1,22 @@
+use std::sync::Arc;
+
 use crate::app_config::{get_bigquery_client, get_pubsub_client};
-use infrastructure::repository::mixpanel_repository::MixpanelRepository;
+use application::services::event_router::EventRouter;
+use domain::{
+    errors::AppError,
+    ports::analytics::{EventSink, EventSource},
+};
+use infrastructure::{
+    repository::{
+        event_queue::WalEventQueue, mixpanel_repository::MixpanelRepository,
+        privacy_store::FilePrivacyStore,
+    },
+    sinks::{
+        bigquery::{BatchConfig, BigQuerySink, TableRouting},
+        bigquery_schema::RowSchema,
+        pubsub::PubSubSink,
+    },
+};
 
 pub mod adapters;
 pub mod app_config;
@@ -39,18 +56,80 @@ async fn main() -> anyhow::Result<()> {
         port: &env_config.server_port.clone(),
     };
 
-    let mixpanel_repository = MixpanelRepository::new(env_config.mixpanel_project_token.clone());
+    let mixpanel_repository = MixpanelRepository::new(
+        env_config.mixpanel_project_token.clone(),
+        env_config.mixpanel_region.clone(),
+        env_config.mixpanel_credentials.clone(),
+        env_config.mixpanel_id_merge,
+    )?;
+
+    let privacy_service = application::services::privacy_service::PrivacyService::new(
+        mixpanel_repository.clone(),
+        FilePrivacyStore::open(&env_config.privacy_store_path)?,
+    );
+
+    let table_routing = TableRouting {
+        default: env_config.bigquery_table.clone(),
+        event_prefix: env_config.bigquery_event_prefix.clone(),
+        environment: env_config.environment.clone(),
+        routes: env_config.bigquery_routes.clone(),
+    };
+    let row_schema = RowSchema::new(
+        env_config.bigquery_columns.clone(),
+        env_config.bigquery_json_column.clone(),
+        env_config.bigquery_keep_columns_in_json,
+    )?;
+    for table in table_routing.tables() {
+        match row_schema.ensure_table(&bigquery_client, &table).await {
+            Ok(()) => {}
+            // Every row would be rejected, so BIGQUERY_COLUMNS has to change first.
+            Err(e @ AppError::SchemaConflict(_)) => return Err(e.into()),
+            Err(e) => tracing::error!(
+                "Failed to prepare BigQuery table {}.{}: {}",
+                table.dataset,
+                table.table,
+                e
+            ),
+        }
+    }
+    let pubsub_sink = PubSubSink::connect(&pubsub_client, consts::PUBSUB_TOPIC_NAME).await?;
+    let sinks: Vec<Arc<dyn EventSink>> = vec![
+        Arc::new(mixpanel_repository.clone()),
+        Arc::new(BigQuerySink::new(
+            bigquery_client,
+            BatchConfig::default(),
+            table_routing,
+            row_schema,
+        )),
+        Arc::new(pubsub_sink),
+    ];
+    let event_router = EventRouter::new(
+        sinks,
+        &[
+            (EventSource::SendEvent, env_config.send_event_sinks.clone()),
+            (
+                EventSource::SendBigquery,
+                env_config.send_bigquery_sinks.clone(),
+            ),
+        ],
+    )?;
 
     let analytics_service = application::services::mixpanel_analytics_service::MixpanelService::new(
         mixpanel_repository,
     );
 
+    let event_queue = WalEventQueue::open(
+        &env_config.ingest_queue_path,
+        env_config.ingest_queue_capacity,
+    )?;
+
     let http_server = adapters::http::HttpServer::new(
         config,
         env_config,
         analytics_service,
-        bigquery_client,
-        pubsub_client,
+        privacy_service,
+        event_router,
+        event_queue,
         ip_client,
     )
     .await